@@ -0,0 +1,44 @@
+//! Rewriting benchmark for `LSystem::next`.
+//!
+//! Run with `cargo bench --bench rewrite`.  Each generation of the
+//! `plant_2d` grammar roughly doubles in length; linear rewriting keeps the
+//! time per atom flat as the state grows.
+
+use std::time::Instant;
+use wasm_demo::lsystem::{LSystem, MapRules};
+
+const SAMPLES: u32 = 5;
+
+fn rules() -> MapRules<char> {
+    let mut rules = MapRules::new();
+    rules.set_str_prob('X', "F+[[X]-X]-F[-FX]+X", 0.9);
+    rules.set_str_prob('X', "M", 0.025);
+    rules.set_str_prob('X', "X", 0.075);
+    rules.set_str('F', "FF");
+    rules
+}
+
+fn main() {
+    println!("{:>10} {:>12} {:>14} {:>12}", "generation", "atoms", "time (us)", "ns/atom");
+    for generation in 1..=10 {
+        let mut best = f64::MAX;
+        let mut atoms = 0;
+        for _ in 0..SAMPLES {
+            let mut system = LSystem::new_seeded(rules(), vec!['X'], 1);
+            for _ in 1..generation {
+                system.next();
+            }
+            atoms = system.state().len();
+            let start = Instant::now();
+            system.next();
+            best = best.min(start.elapsed().as_secs_f64());
+        }
+        println!(
+            "{:>10} {:>12} {:>14.1} {:>12.2}",
+            generation,
+            atoms,
+            best * 1e6,
+            best * 1e9 / atoms as f64
+        );
+    }
+}
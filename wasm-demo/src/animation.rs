@@ -0,0 +1,581 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use wasm_bindgen::prelude::*;
+use crate::lsystem::Drawing2D;
+use crate::render::{CanvasRenderer, Renderer};
+use crate::scene::{self, LineBatch, MarkerPainters, Style, Transform};
+use crate::utils::{request_animation_frame, window};
+
+
+/// milliseconds between flowers at normal speed, so they pop in one by one
+const FLOWER_INTERVAL: f64 = 200.0;
+
+/// longest gap between frames counted towards a timed animation or the
+/// flowers, so time spent paused or in a background tab doesn't make it
+/// jump ahead
+const MAX_FRAME_GAP: f64 = 100.0;
+
+/// lines stroked together before a timed animation checks its budget
+const STROKE_CHUNK: usize = 32;
+
+/// How quickly lines are drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pacing {
+    /// `speed` lines every frame
+    PerFrame,
+    /// every line drawn within `duration` milliseconds at normal speed,
+    /// however many there are, spending at most about `budget` milliseconds
+    /// drawing in any one frame.  Lines are stroked `STROKE_CHUNK` at a time
+    /// with the clock checked after each stroke, so a frame can overrun by
+    /// one chunk.  The budget doesn't cover filling the polygons grown from
+    /// those lines, nor the browser compositing the canvas afterwards.
+    Timed { duration: f64, budget: f64 },
+}
+
+/// The order in which a plant's lines are grown.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrowthOrder {
+    /// the order the turtle drew them in, finishing each branch before its
+    /// sibling starts
+    Drawing,
+    /// trunk first, then each level of branches in turn
+    Depth,
+    /// by path length from the root, so every tip grows at once
+    Distance,
+    /// by the generation of the derivation each line appeared in, so the
+    /// plant grows the way it was rewritten
+    Generation,
+}
+
+/// reorder the lines of a plant, and the info describing them, to be grown
+/// in `order`, keeping every parent pointing at the same line.  Lines that
+/// tie keep the order they were drawn in, and a drawing without info for
+/// every line is left as it is.
+pub fn order_lines(plant: &Drawing2D, order: GrowthOrder) -> Drawing2D {
+    let info = &plant.info;
+    if info.len() != plant.lines.len() {
+        return plant.clone();
+    }
+    let mut indices: Vec<usize> = (0..plant.lines.len()).collect();
+    match order {
+        GrowthOrder::Depth => indices.sort_by_key(|&i| info[i].depth),
+        GrowthOrder::Distance => indices.sort_by(|&a, &b| info[a].distance.total_cmp(&info[b].distance)),
+        GrowthOrder::Generation => indices.sort_by_key(|&i| info[i].generation),
+        GrowthOrder::Drawing => {}
+    }
+    let mut moved_to = vec![0; indices.len()];
+    for (to, &from) in indices.iter().enumerate() {
+        moved_to[from] = to;
+    }
+
+    let mut ordered = plant.clone();
+    for (to, &from) in indices.iter().enumerate() {
+        ordered.lines[to] = plant.lines[from];
+        ordered.info[to] = info[from];
+        ordered.info[to].parent = info[from].parent.map(|p| moved_to[p]);
+    }
+    for polygon in ordered.polygons.iter_mut() {
+        polygon.parent = polygon.parent.map(|p| moved_to[p]);
+    }
+    ordered
+}
+
+/// The drawing side of a growing plant: which lines, polygons and flowers
+/// have been drawn so far, independent of how frames are scheduled.
+pub struct Growth<R: Renderer> {
+    renderer: R,
+    style: Style,
+    painters: MarkerPainters,
+    plant: Rc<Drawing2D>,
+    /// the plant's polygons in the order they become due, each once the
+    /// line it grows from is drawn
+    polygons: Vec<usize>,
+    transform: Transform,
+    next_line: usize,
+    next_polygon: usize,
+    next_marker: usize,
+    /// lines per frame, and flowers per `FLOWER_INTERVAL`
+    speed: f64,
+    pacing: Pacing,
+    /// fractional lines carried over to the next frame
+    pending: f64,
+    /// milliseconds of growth so far, scaled by the speed
+    elapsed: f64,
+    last_frame: Option<f64>,
+    /// milliseconds since the last flower, scaled by the speed
+    since_flower: f64,
+}
+
+impl<R: Renderer> Growth<R> {
+    /// Draw the background and any pot, ready to grow the plant over them.  The
+    /// renderer's stroke is left set up for the stems.
+    pub fn new(
+        mut renderer: R,
+        style: Style,
+        plant: Rc<Drawing2D>,
+        width: f64,
+        height: f64,
+    ) -> Growth<R> {
+        let transform = style.plant_transform(&plant, width, height);
+        scene::draw_background(&mut renderer, &style, width, height);
+        if style.potted {
+            scene::draw_pot(&mut renderer, &style, transform.offset.x, transform.offset.y);
+        }
+
+        let mut polygons: Vec<usize> = (0..plant.polygons.len()).collect();
+        polygons.sort_by_key(|&i| plant.polygons[i].parent.map_or(0, |p| p + 1));
+
+        Growth {
+            renderer,
+            style,
+            painters: MarkerPainters::default(),
+            plant,
+            polygons,
+            transform,
+            next_line: 0,
+            next_polygon: 0,
+            next_marker: 0,
+            speed: 1.0,
+            pacing: Pacing::PerFrame,
+            pending: 0.0,
+            elapsed: 0.0,
+            last_frame: None,
+            since_flower: 0.0,
+        }
+    }
+
+    pub fn renderer(&self) -> &R {
+        &self.renderer
+    }
+
+    /// paint markers with `painters` rather than the defaults
+    pub fn set_painters(&mut self, painters: MarkerPainters) {
+        self.painters = painters;
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.max(0.0);
+    }
+
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacing = pacing;
+    }
+
+    /// the fraction of lines, polygons and flowers drawn so far
+    pub fn progress(&self) -> f64 {
+        let total = self.plant.lines.len() + self.polygons.len() + self.plant.markers.len();
+        if total == 0 {
+            return 1.0;
+        }
+        (self.next_line + self.next_polygon + self.next_marker) as f64 / total as f64
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.next_line == self.plant.lines.len()
+            && self.next_polygon == self.polygons.len()
+            && self.next_marker == self.plant.markers.len()
+    }
+
+    /// draw up to `count` more lines in new paths, so segments already on
+    /// the canvas aren't stroked again, then fill the polygons growing from
+    /// them.  With a finite `deadline` the lines are stroked in chunks,
+    /// stopping after the first chunk to end past it by `clock`.
+    fn draw_lines(&mut self, count: usize, clock: &dyn Fn() -> f64, deadline: f64) {
+        let plant = Rc::clone(&self.plant);
+        let end = (self.next_line + count).min(plant.lines.len());
+        let mut batch = LineBatch::new();
+        let mut chunk = 0;
+        while self.next_line < end {
+            let i = self.next_line;
+            let line = self.transform.apply_line(&plant.lines[i]);
+            let (width, color) = (scene::line_width(&self.style, &plant.info, i), scene::line_color(&self.style, &plant.info, i));
+            batch.add(&mut self.renderer, &line, width, color);
+            self.next_line += 1;
+            chunk += 1;
+            if deadline.is_finite() && chunk == STROKE_CHUNK {
+                // the stroke is where the canvas spends its time, so the
+                // clock is only worth reading after it
+                batch.flush(&mut self.renderer);
+                chunk = 0;
+                if clock() > deadline {
+                    break;
+                }
+            }
+        }
+        batch.finish(&mut self.renderer);
+
+        while let Some(&i) = self.polygons.get(self.next_polygon) {
+            let polygon = &plant.polygons[i];
+            if polygon.parent.is_some_and(|p| p >= self.next_line) {
+                break;
+            }
+            scene::draw_polygon(&mut self.renderer, &self.style, &self.transform.apply_polygon(polygon));
+            self.next_polygon += 1;
+        }
+    }
+
+    fn draw_marker(&mut self) {
+        let marker = &self.plant.markers[self.next_marker];
+        let radius = self.style.flower_radius * marker.scale;
+        self.painters.paint(&mut self.renderer, &self.style, &self.transform.apply_marker(marker), radius);
+        self.next_marker += 1;
+    }
+
+    /// Draw one frame's worth of the plant, reading the time in milliseconds
+    /// from `clock`: lines first, with polygons as the lines they grow from
+    /// are drawn, then flowers once every line is down so they don't pop in
+    /// under later branches.
+    pub fn advance(&mut self, clock: &dyn Fn() -> f64) {
+        let now = clock();
+        let gap = self.last_frame.map_or(0.0, |last| (now - last).clamp(0.0, MAX_FRAME_GAP));
+        self.last_frame = Some(now);
+
+        let lines = self.plant.lines.len();
+        if self.next_line < lines || self.next_polygon < self.polygons.len() {
+            match self.pacing {
+                Pacing::PerFrame => {
+                    self.pending += self.speed;
+                    let count = self.pending as usize;
+                    self.pending -= count as f64;
+                    self.draw_lines(count, clock, f64::INFINITY);
+                }
+                Pacing::Timed { duration, budget } => {
+                    self.elapsed += gap * self.speed;
+                    let due = if duration > 0.0 {
+                        (lines as f64 * self.elapsed / duration).ceil() as usize
+                    } else {
+                        lines
+                    };
+                    self.draw_lines(due.saturating_sub(self.next_line), clock, now + budget);
+                }
+            }
+            if self.next_line == lines {
+                self.since_flower = 0.0;
+            }
+        } else if self.next_marker < self.plant.markers.len() {
+            self.since_flower += gap * self.speed;
+            while self.next_marker < self.plant.markers.len() && self.since_flower >= FLOWER_INTERVAL {
+                self.draw_marker();
+                self.since_flower -= FLOWER_INTERVAL;
+            }
+        }
+    }
+
+    /// draw everything that is left at once
+    pub fn finish(&mut self) {
+        self.draw_lines(self.plant.lines.len(), &|| 0.0, f64::INFINITY);
+        while self.next_marker < self.plant.markers.len() {
+            self.draw_marker();
+        }
+    }
+}
+
+
+struct Animation {
+    growth: Growth<CanvasRenderer>,
+    paused: bool,
+    /// finished or cancelled; the next frame releases the closure
+    done: bool,
+    /// whether a frame has been requested and not yet run
+    scheduled: bool,
+    frame: Option<Closure<dyn FnMut()>>,
+}
+
+impl Animation {
+    fn schedule(&mut self) {
+        if let Some(frame) = self.frame.as_ref() {
+            request_animation_frame(frame);
+            self.scheduled = true;
+        }
+    }
+
+    /// Stop for good.  The closure can only be dropped once no frame is
+    /// pending, otherwise the browser would call into a freed closure.
+    fn stop(&mut self) {
+        self.done = true;
+        if !self.scheduled {
+            let _ = self.frame.take();
+        }
+    }
+}
+
+thread_local! {
+    /// the animation currently drawing on each canvas element
+    static RUNNING: RefCell<HashMap<String, Weak<RefCell<Animation>>>> = RefCell::new(HashMap::new());
+}
+
+/// A plant growing on a canvas, one frame at a time.  Starting another plant
+/// on the same canvas cancels this one.
+#[wasm_bindgen]
+pub struct PlantAnimation {
+    state: Rc<RefCell<Animation>>,
+    seed: u32,
+}
+
+impl PlantAnimation {
+    /// start growing `growth` on the canvas `element`, cancelling any
+    /// animation already running there
+    pub fn start(growth: Growth<CanvasRenderer>, element: &str, seed: u32) -> PlantAnimation {
+        let state = Rc::new(RefCell::new(Animation {
+            growth,
+            paused: false,
+            done: false,
+            scheduled: false,
+            frame: None,
+        }));
+
+        let previous = RUNNING.with(|running| {
+            running.borrow_mut().insert(element.to_string(), Rc::downgrade(&state))
+        });
+        if let Some(previous) = previous.and_then(|p| p.upgrade()) {
+            previous.borrow_mut().stop();
+        }
+
+        // The closure keeps the animation alive while frames are requested,
+        // and the animation keeps the closure until it stops, when the cycle
+        // is broken by dropping the closure.
+        let frame_state = state.clone();
+        let frame = Closure::wrap(Box::new(move || {
+            let mut animation = frame_state.borrow_mut();
+            animation.scheduled = false;
+            if animation.done {
+                let _ = animation.frame.take();
+                return;
+            }
+            if animation.paused {
+                // `resume` schedules the next frame
+                return;
+            }
+
+            let performance = window().performance().unwrap();
+            animation.growth.advance(&|| performance.now());
+            if animation.growth.is_complete() {
+                animation.stop();
+            } else {
+                animation.schedule();
+            }
+        }) as Box<dyn FnMut()>);
+
+        {
+            let mut animation = state.borrow_mut();
+            animation.frame = Some(frame);
+            animation.schedule();
+        }
+        PlantAnimation { state, seed }
+    }
+}
+
+#[wasm_bindgen]
+impl PlantAnimation {
+    /// the seed the plant was generated from
+    #[wasm_bindgen(getter)]
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    /// the fraction of the plant drawn so far, from 0 to 1
+    #[wasm_bindgen(getter)]
+    pub fn progress(&self) -> f64 {
+        self.state.borrow().growth.progress()
+    }
+
+    pub fn pause(&self) {
+        self.state.borrow_mut().paused = true;
+    }
+
+    pub fn resume(&self) {
+        let mut animation = self.state.borrow_mut();
+        animation.paused = false;
+        if !animation.done && !animation.scheduled {
+            animation.schedule();
+        }
+    }
+
+    /// set how fast the plant grows, where 1 is normal speed
+    pub fn set_speed(&self, speed: f64) {
+        self.state.borrow_mut().growth.set_speed(speed);
+    }
+
+    /// draw `speed` lines every frame, as plants do by default
+    pub fn pace_by_frame(&self) {
+        self.state.borrow_mut().growth.set_pacing(Pacing::PerFrame);
+    }
+
+    /// draw every line within `duration` milliseconds whatever their number,
+    /// drawing for at most `budget` milliseconds each frame
+    pub fn pace_by_duration(&self, duration: f64, budget: f64) {
+        self.state.borrow_mut().growth.set_pacing(Pacing::Timed { duration, budget });
+    }
+
+    /// draw the rest of the plant immediately
+    pub fn finish(&self) {
+        let mut animation = self.state.borrow_mut();
+        if !animation.done {
+            animation.growth.finish();
+            animation.stop();
+        }
+    }
+
+    /// stop drawing, leaving the canvas as it is
+    pub fn cancel(&self) {
+        self.state.borrow_mut().stop();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use crate::lsystem::{Bearing2D, Drawing, Line2D, LineInfo, Marker, MarkerKind, Polygon, Position2D};
+    use crate::render::{DrawCommand, RecordingRenderer};
+
+    fn growth(lines: usize, markers: usize) -> Growth<RecordingRenderer> {
+        let line = Line2D {
+            start: Position2D { x: 0.0, y: 0.0 },
+            end: Position2D { x: 0.0, y: -10.0 },
+        };
+        let flower = Marker::new(MarkerKind::Flower, line.end, Bearing2D { rotation: 0.0 });
+        let plant = Drawing { lines: vec![line; lines], markers: vec![flower; markers], ..Drawing::default() };
+        Growth::new(RecordingRenderer::new(), Style::default(), Rc::new(plant), 200.0, 100.0)
+    }
+
+    fn count(growth: &Growth<RecordingRenderer>, command: DrawCommand) -> usize {
+        growth.renderer().commands.iter().filter(|c| **c == command).count()
+    }
+
+    #[test]
+    fn speed_sets_lines_per_frame() {
+        let time = Cell::new(0.0);
+        let clock = || time.get();
+        let mut growth = growth(5, 2);
+        growth.set_speed(1.5);
+        growth.advance(&clock);
+        time.set(16.0);
+        growth.advance(&clock);
+        // each frame strokes only its own lines, after the pot
+        assert_eq!(count(&growth, DrawCommand::LineTo(100.0, 40.0)), 3);
+        assert_eq!(count(&growth, DrawCommand::Stroke), 1 + 2);
+        assert_eq!(growth.progress(), 3.0 / 7.0);
+
+        // flowers follow the lines at intervals shortened by the speed
+        growth.set_speed(2.0);
+        time.set(32.0);
+        growth.advance(&clock);
+        time.set(48.0);
+        growth.advance(&clock);
+        assert_eq!(growth.progress(), 5.0 / 7.0);
+        time.set(148.0);
+        growth.advance(&clock);
+        assert_eq!(growth.progress(), 6.0 / 7.0);
+    }
+
+    #[test]
+    fn timed_growth_keeps_to_its_duration_and_budget() {
+        let time = Cell::new(0.0);
+        let clock = || time.get();
+        let mut growth = growth(100, 0);
+        growth.set_pacing(Pacing::Timed { duration: 1000.0, budget: 10.0 });
+        growth.advance(&clock);
+        time.set(50.0);
+        growth.advance(&clock);
+        assert_eq!(count(&growth, DrawCommand::LineTo(100.0, 40.0)), 5);
+        // long gaps only count for `MAX_FRAME_GAP`
+        time.set(250.0);
+        growth.advance(&clock);
+        assert_eq!(count(&growth, DrawCommand::LineTo(100.0, 40.0)), 15);
+        assert_eq!(count(&growth, DrawCommand::Stroke), 1 + 2);
+
+        // a slow renderer runs out of budget before catching up, checking
+        // the clock after stroking each chunk
+        let mut growth = self::growth(300, 0);
+        growth.set_pacing(Pacing::Timed { duration: 1.0, budget: 2.5 });
+        time.set(0.0);
+        growth.advance(&clock);
+        let slow_clock = || {
+            time.set(time.get() + 1.0);
+            time.get()
+        };
+        time.set(16.0);
+        growth.advance(&slow_clock);
+        assert_eq!(count(&growth, DrawCommand::LineTo(100.0, 40.0)), 3 * STROKE_CHUNK);
+        assert_eq!(count(&growth, DrawCommand::Stroke), 1 + 3);
+    }
+
+    #[test]
+    fn flowers_skip_long_frame_gaps() {
+        let time = Cell::new(0.0);
+        let clock = || time.get();
+        let mut growth = growth(0, 5);
+        growth.advance(&clock);
+        // a backgrounded tab only counts for `MAX_FRAME_GAP`, so the
+        // flowers keep popping in one by one when it comes back
+        time.set(60_000.0);
+        growth.advance(&clock);
+        assert_eq!(growth.progress(), 0.0);
+        time.set(120_000.0);
+        growth.advance(&clock);
+        assert_eq!(growth.progress(), 1.0 / 5.0);
+    }
+
+    #[test]
+    fn orders_lines_for_growing() {
+        let lines: Vec<Line2D> = (0..4).map(|i| Line2D {
+            start: Position2D { x: f64::from(i), y: 0.0 },
+            end: Position2D { x: f64::from(i), y: 1.0 },
+        }).collect();
+        let info = [
+            LineInfo { depth: 0, distance: 0.0, width: 1.0, parent: None, color: 0, generation: 0 },
+            LineInfo { depth: 1, distance: 3.0, width: 1.0, parent: None, color: 0, generation: 1 },
+            LineInfo { depth: 2, distance: 1.0, width: 1.0, parent: None, color: 0, generation: 2 },
+            LineInfo { depth: 1, distance: 1.0, width: 1.0, parent: None, color: 0, generation: 0 },
+        ];
+        let leaf = Polygon { points: vec![], color: 0, parent: Some(1) };
+        let plant = Drawing { lines, info: info.to_vec(), markers: vec![], polygons: vec![leaf] };
+        let xs = |order| order_lines(&plant, order).lines.iter().map(|l| l.start.x).collect::<Vec<_>>();
+        assert_eq!(xs(GrowthOrder::Drawing), vec![0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(xs(GrowthOrder::Depth), vec![0.0, 1.0, 3.0, 2.0]);
+        assert_eq!(xs(GrowthOrder::Distance), vec![0.0, 2.0, 3.0, 1.0]);
+        assert_eq!(xs(GrowthOrder::Generation), vec![0.0, 3.0, 1.0, 2.0]);
+        // the leaf still grows from the line drawn at x = 1
+        assert_eq!(order_lines(&plant, GrowthOrder::Distance).polygons[0].parent, Some(3));
+
+        // lines drawn without info keep their order
+        let bare = Drawing { info: vec![], ..plant.clone() };
+        assert_eq!(order_lines(&bare, GrowthOrder::Depth), bare);
+        assert_eq!(order_lines(&bare, GrowthOrder::Drawing), bare);
+    }
+
+    #[test]
+    fn polygons_grow_with_their_lines() {
+        let line = Line2D {
+            start: Position2D { x: 0.0, y: 0.0 },
+            end: Position2D { x: 0.0, y: -10.0 },
+        };
+        let leaf = Polygon { points: vec![line.start, line.end, Position2D { x: 5.0, y: -5.0 }], color: 0, parent: Some(1) };
+        let plant = Drawing { lines: vec![line; 2], polygons: vec![leaf], ..Drawing::default() };
+        let mut growth = Growth::new(RecordingRenderer::new(), Style::default(), Rc::new(plant), 200.0, 100.0);
+        // only the pot is filled until the second line is down
+        growth.advance(&|| 0.0);
+        assert_eq!(count(&growth, DrawCommand::Fill), 1);
+        assert_eq!(growth.progress(), 1.0 / 3.0);
+        growth.advance(&|| 16.0);
+        assert_eq!(count(&growth, DrawCommand::Fill), 2);
+        assert!(growth.is_complete());
+    }
+
+    #[test]
+    fn finishing_draws_everything() {
+        let mut growth = growth(3, 2);
+        growth.advance(&|| 0.0);
+        growth.finish();
+        assert!(growth.is_complete());
+        assert_eq!(growth.progress(), 1.0);
+        // the pot and the remaining lines are stroked, and the pollen of two
+        // flowers is drawn
+        assert_eq!(count(&growth, DrawCommand::Stroke), 3);
+        assert_eq!(growth.renderer().commands.iter().filter(|c| matches!(c, DrawCommand::Arc(..))).count(), 2);
+    }
+}
@@ -0,0 +1,250 @@
+//! Generate plants from a grammar file outside the browser, with the same
+//! L-system and turtle code the site uses.
+//!
+//! ```text
+//! lsystem GRAMMAR [--seed N] [--rounds N] [--format svg|json|ppm|png] [--output FILE]
+//!                 [--size WIDTHxHEIGHT] [--fit contain|cover|fixed] [--scale N]
+//! ```
+//!
+//! `GRAMMAR` is a file in the format accepted by `Grammar::parse`, or `-` for
+//! standard input.  Without `--seed` a random seed is used and reported on
+//! standard error; `--rounds` defaults to the grammar's `iterations`.  The
+//! format defaults to the extension of `--output`, or SVG, and output goes to
+//! standard output unless `--output` is given.
+//!
+//! Images are sized to fit the plant unless `--size` is given.  `--fit`
+//! scales the plant into the image as on the site, and `--scale` sets the
+//! scale used by `--fit fixed`, the default.
+
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::process;
+use wasm_demo::generate_plant_from_grammar;
+use wasm_demo::grammar::Grammar;
+use wasm_demo::lsystem::{Drawing2D, Position2D};
+use wasm_demo::raster::RasterRenderer;
+use wasm_demo::scene::{FitMode, Style};
+use wasm_demo::{scene, svg};
+
+const USAGE: &str = "usage: lsystem GRAMMAR [--seed N] [--rounds N] [--format svg|json|ppm|png] [--output FILE] \
+                     [--size WIDTHxHEIGHT] [--fit contain|cover|fixed] [--scale N]";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Svg,
+    Json,
+    Ppm,
+    Png,
+}
+
+impl Format {
+    fn parse(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "svg" => Some(Format::Svg),
+            "json" => Some(Format::Json),
+            "ppm" => Some(Format::Ppm),
+            "png" => Some(Format::Png),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Args {
+    grammar: String,
+    seed: Option<u64>,
+    rounds: Option<u8>,
+    format: Option<Format>,
+    output: Option<String>,
+    size: Option<(f64, f64)>,
+    fit: FitMode,
+    scale: f64,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        grammar: String::new(),
+        seed: None,
+        rounds: None,
+        format: None,
+        output: None,
+        size: None,
+        fit: FitMode::Fixed,
+        scale: 1.0,
+    };
+    let mut grammar = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("`{}` needs a value", name));
+        match arg.as_str() {
+            "--seed" => {
+                let v = value("--seed")?;
+                parsed.seed = Some(v.parse().map_err(|_| format!("invalid seed `{}`", v))?);
+            }
+            "--rounds" => {
+                let v = value("--rounds")?;
+                parsed.rounds = Some(v.parse().map_err(|_| format!("invalid number of rounds `{}`", v))?);
+            }
+            "--format" => {
+                let v = value("--format")?;
+                parsed.format = Some(Format::parse(&v).ok_or(format!("unknown format `{}`", v))?);
+            }
+            "--output" | "-o" => parsed.output = Some(value("--output")?),
+            "--size" => {
+                let v = value("--size")?;
+                let size = v.split_once('x')
+                    .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+                    .filter(|&(w, h)| w > 0 && h > 0)
+                    .ok_or(format!("invalid size `{}`, expected WIDTHxHEIGHT", v))?;
+                parsed.size = Some((f64::from(size.0), f64::from(size.1)));
+            }
+            "--fit" => {
+                let v = value("--fit")?;
+                parsed.fit = match v.as_str() {
+                    "contain" => FitMode::Contain,
+                    "cover" => FitMode::Cover,
+                    "fixed" => FitMode::Fixed,
+                    _ => return Err(format!("unknown fit `{}`", v)),
+                };
+            }
+            "--scale" => {
+                let v = value("--scale")?;
+                parsed.scale = v.parse().ok().filter(|s: &f64| *s > 0.0).ok_or(format!("invalid scale `{}`", v))?;
+            }
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+            _ if grammar.is_none() => grammar = Some(arg),
+            _ => return Err(format!("unexpected argument `{}`", arg)),
+        }
+    }
+    parsed.grammar = grammar.ok_or_else(|| USAGE.to_string())?;
+    Ok(parsed)
+}
+
+/// the plant as JSON, with lines as `[x1, y1, x2, y2, width, color]`,
+/// markers as `{"kind": k, "position": [x, y], "rotation": r, "scale": s,
+/// "color": c}` and polygons as `{"color": c, "points": [[x, y]...]}`
+/// relative to the root, where widths are relative to the base line width,
+/// rotations are in radians and colours index the palette.  A marker's
+/// colour is `null` when it keeps the colour of its kind, and so is any
+/// number that isn't finite.
+fn plant_json(seed: u64, plant: &Drawing2D) -> String {
+    let point = |p: &Position2D| format!("[{},{}]", number(p.x), number(p.y));
+    let mut out = format!("{{\"seed\":{},\"lines\":[", seed);
+    for (i, (l, info)) in plant.lines.iter().zip(&plant.info).enumerate() {
+        let sep = if i == 0 { "" } else { "," };
+        let coordinates = [l.start.x, l.start.y, l.end.x, l.end.y, info.width].map(number);
+        write!(out, "{}[{},{}]", sep, coordinates.join(","), info.color).unwrap();
+    }
+    out.push_str("],\"markers\":[");
+    for (i, m) in plant.markers.iter().enumerate() {
+        let sep = if i == 0 { "" } else { "," };
+        let color = m.color.map_or("null".to_string(), |c| c.to_string());
+        write!(
+            out,
+            "{}{{\"kind\":\"{}\",\"position\":{},\"rotation\":{},\"scale\":{},\"color\":{}}}",
+            sep, m.kind, point(&m.position), number(m.bearing.rotation), number(m.scale), color,
+        ).unwrap();
+    }
+    out.push_str("],\"polygons\":[");
+    for (i, polygon) in plant.polygons.iter().enumerate() {
+        let sep = if i == 0 { "" } else { "," };
+        let points: Vec<String> = polygon.points.iter().map(point).collect();
+        write!(out, "{}{{\"color\":{},\"points\":[{}]}}", sep, polygon.color, points.join(",")).unwrap();
+    }
+    out.push_str("]}\n");
+    out
+}
+
+/// a number as JSON, which has no NaN or infinity
+fn number(x: f64) -> String {
+    if x.is_finite() { x.to_string() } else { "null".to_string() }
+}
+
+fn run() -> Result<(), String> {
+    let args = parse_args(std::env::args().skip(1))?;
+
+    let mut src = String::new();
+    if args.grammar == "-" {
+        std::io::stdin().read_to_string(&mut src).map_err(|e| format!("reading standard input: {}", e))?;
+    } else {
+        src = std::fs::read_to_string(&args.grammar).map_err(|e| format!("{}: {}", args.grammar, e))?;
+    }
+    let grammar = Grammar::parse(&src).map_err(|e| format!("{}: {}", args.grammar, e))?;
+
+    let seed = args.seed.unwrap_or_else(|| {
+        let seed = u64::from(rand::random::<u32>());
+        eprintln!("seed: {}", seed);
+        seed
+    });
+    let rounds = args.rounds.unwrap_or(grammar.iterations);
+    let plant = generate_plant_from_grammar(&grammar, seed, rounds);
+    let style = Style { fit: args.fit, scale: args.scale, palette: grammar.palette.clone(), ..Style::default() };
+    let (width, height) = args.size.unwrap_or_else(|| style.plant_size(&plant));
+
+    let format = args.format
+        .or_else(|| args.output.as_deref().and_then(|o| o.rsplit_once('.')).and_then(|(_, ext)| Format::parse(ext)))
+        .unwrap_or(Format::Svg);
+    let bytes = match format {
+        Format::Svg => svg::plant_svg_sized(&style, &plant, width, height).into_bytes(),
+        Format::Json => plant_json(seed, &plant).into_bytes(),
+        Format::Ppm | Format::Png => {
+            let mut image = RasterRenderer::new(width as usize, height as usize);
+            scene::draw_plant(&mut image, &style, &plant, width, height);
+            if format == Format::Ppm { image.to_ppm() } else { image.to_png() }
+        }
+    };
+
+    match args.output {
+        Some(path) => std::fs::write(&path, bytes).map_err(|e| format!("{}: {}", path, e)),
+        None => std::io::stdout().write_all(&bytes).map_err(|e| format!("writing standard output: {}", e)),
+    }
+}
+
+fn main() {
+    if let Err(message) = run() {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_demo::lsystem::{Bearing2D, Drawing, Line2D, LineInfo, Marker, MarkerKind};
+
+    fn parse(args: &str) -> Result<Args, String> {
+        parse_args(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let args = parse("plant.txt --seed 7 --size 300x200 --fit contain -o plant.png").unwrap();
+        assert_eq!(args.grammar, "plant.txt");
+        assert_eq!(args.seed, Some(7));
+        assert_eq!(args.size, Some((300.0, 200.0)));
+        assert_eq!(args.fit, FitMode::Contain);
+        assert_eq!(args.output.as_deref(), Some("plant.png"));
+
+        assert_eq!(parse("plant.txt --size 300").unwrap_err(), "invalid size `300`, expected WIDTHxHEIGHT");
+        assert_eq!(parse("plant.txt --size 0x200").unwrap_err(), "invalid size `0x200`, expected WIDTHxHEIGHT");
+        assert_eq!(parse("plant.txt --fit stretch").unwrap_err(), "unknown fit `stretch`");
+        assert_eq!(parse("plant.txt --seed").unwrap_err(), "`--seed` needs a value");
+        assert_eq!(parse("plant.txt other.txt").unwrap_err(), "unexpected argument `other.txt`");
+        assert_eq!(parse("--seed 7").unwrap_err(), USAGE);
+    }
+
+    #[test]
+    fn writes_plants_as_json() {
+        let line = Line2D { start: Position2D { x: 0.0, y: 0.0 }, end: Position2D { x: 0.5, y: -2.0 } };
+        let info = LineInfo { depth: 0, distance: 0.0, width: 1.0, parent: None, color: 1, generation: 0 };
+        let flower = Marker::new(MarkerKind::Flower, Position2D { x: f64::NAN, y: 1.0 }, Bearing2D { rotation: f64::INFINITY });
+        let plant = Drawing { lines: vec![line], info: vec![info], markers: vec![flower], polygons: vec![] };
+        assert_eq!(
+            plant_json(3, &plant),
+            "{\"seed\":3,\"lines\":[[0,0,0.5,-2,1,1]],\
+             \"markers\":[{\"kind\":\"flower\",\"position\":[null,1],\"rotation\":null,\"scale\":1,\"color\":null}],\
+             \"polygons\":[]}\n",
+        );
+    }
+}
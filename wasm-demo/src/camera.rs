@@ -0,0 +1,169 @@
+use wasm_bindgen::prelude::*;
+use crate::lsystem::{Line2D, Line3D, Position2D, Position3D};
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// vertical field of view in radians
+    Perspective { fov: f64 },
+    /// height of the world visible on screen, in world units
+    Orthographic { height: f64 },
+}
+
+/// A camera looking from `eye` towards `target`, projecting 3D geometry onto
+/// a canvas of a given size.  Screen coordinates have their origin at the top
+/// left of the canvas with y pointing down, like Canvas2D.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    eye: Position3D,
+    target: Position3D,
+    up: Position3D,
+    projection: Projection,
+}
+
+/// A point projected onto the screen, with its distance in front of the
+/// camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Projected {
+    pub position: Position2D,
+    pub depth: f64,
+}
+
+#[wasm_bindgen]
+impl Camera {
+    /// a perspective camera with a vertical field of view in degrees
+    #[wasm_bindgen(constructor)]
+    pub fn new(eye_x: f64, eye_y: f64, eye_z: f64, target_x: f64, target_y: f64, target_z: f64, fov: f64) -> Camera {
+        Camera::perspective(
+            Position3D::new(eye_x, eye_y, eye_z),
+            Position3D::new(target_x, target_y, target_z),
+            fov.to_radians(),
+        )
+    }
+
+    /// an orthographic camera showing `height` world units from top to
+    /// bottom of the canvas
+    #[wasm_bindgen(js_name = orthographic)]
+    pub fn new_orthographic(eye_x: f64, eye_y: f64, eye_z: f64, target_x: f64, target_y: f64, target_z: f64, height: f64) -> Camera {
+        Camera::orthographic(
+            Position3D::new(eye_x, eye_y, eye_z),
+            Position3D::new(target_x, target_y, target_z),
+            height,
+        )
+    }
+}
+
+impl Camera {
+    /// a perspective camera with a vertical field of view in radians, whose
+    /// up direction is the y axis
+    pub fn perspective(eye: Position3D, target: Position3D, fov: f64) -> Camera {
+        Camera {
+            eye,
+            target,
+            up: Position3D::new(0.0, 1.0, 0.0),
+            projection: Projection::Perspective { fov },
+        }
+    }
+
+    /// an orthographic camera whose up direction is the y axis
+    pub fn orthographic(eye: Position3D, target: Position3D, height: f64) -> Camera {
+        Camera {
+            eye,
+            target,
+            up: Position3D::new(0.0, 1.0, 0.0),
+            projection: Projection::Orthographic { height },
+        }
+    }
+
+    /// the same camera with a different up direction
+    pub fn with_up(self, up: Position3D) -> Camera {
+        Camera { up, ..self }
+    }
+
+    /// project a point onto a canvas of the given size, or `None` if it lies
+    /// behind a perspective camera.  A camera looking straight along its up
+    /// direction takes -z, or failing that x, as up instead.
+    pub fn project(&self, p: &Position3D, width: f64, height: f64) -> Option<Projected> {
+        let forward = self.target.sub(&self.eye).normalized();
+        // looking straight along the up direction leaves no way to tell
+        // right from left, so fall back to the first axis that does
+        let right = [self.up, Position3D::new(0.0, 0.0, -1.0), Position3D::new(1.0, 0.0, 0.0)].iter()
+            .map(|up| forward.cross(up))
+            .find(|right| right.length() > 1e-9)?
+            .normalized();
+        let up = right.cross(&forward);
+
+        let d = p.sub(&self.eye);
+        let (x, y, depth) = (d.dot(&right), d.dot(&up), d.dot(&forward));
+        let (x, y) = match self.projection {
+            Projection::Perspective { fov } => {
+                if depth <= 1e-6 {
+                    return None;
+                }
+                let focal = height / 2.0 / (fov / 2.0).tan();
+                (x / depth * focal, y / depth * focal)
+            }
+            Projection::Orthographic { height: world_height } => {
+                let scale = height / world_height;
+                (x * scale, y * scale)
+            }
+        };
+        Some(Projected {
+            position: Position2D { x: width / 2.0 + x, y: height / 2.0 - y },
+            depth,
+        })
+    }
+
+    /// project lines onto a canvas of the given size, each with its index
+    /// and depth, sorted from the farthest to the nearest so drawing them in
+    /// order lets near branches cover far ones.  Lines reaching behind a
+    /// perspective camera are dropped.
+    pub fn project_lines(&self, lines: &[Line3D], width: f64, height: f64) -> Vec<(usize, Line2D, f64)> {
+        let mut projected: Vec<(usize, Line2D, f64)> = lines.iter().enumerate().filter_map(|(i, l)| {
+            let start = self.project(&l.start, width, height)?;
+            let end = self.project(&l.end, width, height)?;
+            let line = Line2D { start: start.position, end: end.position };
+            Some((i, line, (start.depth + end.depth) / 2.0))
+        }).collect();
+        projected.sort_by(|a, b| b.2.total_cmp(&a.2));
+        projected
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projects_the_target_to_the_centre() {
+        let camera = Camera::new(0.0, 0.0, 10.0, 0.0, 0.0, 0.0, 60.0);
+        let p = camera.project(&Position3D::new(0.0, 0.0, 0.0), 200.0, 100.0).unwrap();
+        assert_eq!(p.position, Position2D { x: 100.0, y: 50.0 });
+        assert_eq!(p.depth, 10.0);
+        // world up is screen up, and points behind the eye are dropped
+        let above = camera.project(&Position3D::new(0.0, 1.0, 0.0), 200.0, 100.0).unwrap();
+        assert!(above.position.y < 50.0);
+        assert!(camera.project(&Position3D::new(0.0, 0.0, 11.0), 200.0, 100.0).is_none());
+    }
+
+    #[test]
+    fn looks_straight_down() {
+        let camera = Camera::orthographic(Position3D::new(0.0, 10.0, 0.0), Position3D::new(0.0, 0.0, 0.0), 10.0);
+        let p = |x, z| camera.project(&Position3D::new(x, 0.0, z), 100.0, 100.0).unwrap().position;
+        // seen from above, x is still right and -z is up the canvas
+        assert_eq!(p(0.0, 0.0), Position2D { x: 50.0, y: 50.0 });
+        assert_eq!(p(1.0, 0.0), Position2D { x: 60.0, y: 50.0 });
+        assert_eq!(p(0.0, -1.0), Position2D { x: 50.0, y: 40.0 });
+    }
+
+    #[test]
+    fn sorts_lines_far_to_near() {
+        let camera = Camera::orthographic(Position3D::new(0.0, 0.0, 10.0), Position3D::new(0.0, 0.0, 0.0), 10.0);
+        let near = Line3D { start: Position3D::new(0.0, 0.0, 1.0), end: Position3D::new(1.0, 0.0, 1.0) };
+        let far = Line3D { start: Position3D::new(0.0, 0.0, -1.0), end: Position3D::new(1.0, 0.0, -1.0) };
+        let projected = camera.project_lines(&[near, far], 100.0, 100.0);
+        assert_eq!(projected.iter().map(|p| (p.0, p.2)).collect::<Vec<_>>(), vec![(1, 11.0), (0, 9.0)]);
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use rand::prelude::*;
+use crate::lsystem::{LRules, Selection, Symbol, WeightError};
+
+
+/// A context-sensitive production `left < predecessor > right -> successor`.
+/// An empty left or right context always matches, so context-free
+/// productions can live in the same ruleset.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContextRule<T> {
+    pub left: Vec<char>,
+    pub predecessor: char,
+    pub right: Vec<char>,
+    pub successor: Vec<T>,
+    /// the production's weight, as chosen by the ruleset's `Selection`
+    pub probability: f64,
+}
+
+impl<T> ContextRule<T> {
+    fn is_context_free(&self) -> bool {
+        self.left.is_empty() && self.right.is_empty()
+    }
+}
+
+/// An error raised while parsing a context-sensitive production.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContextRuleError {
+    pub message: String,
+}
+
+impl fmt::Display for ContextRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ContextRuleError {}
+
+
+/// Production rules for 1L and 2L-systems.  Context is searched through the
+/// bracket structure of the state as described in The Algorithmic Beauty of
+/// Plants: the left context of an atom is found along the path back to the
+/// root, skipping sibling branches, and the right context continues along
+/// the current axis, skipping lateral branches unless the context itself
+/// names them.  Symbols in the ignore set (typically `+ - F`) are skipped
+/// altogether.  A matching context-sensitive production takes precedence
+/// over the context-free ones, and one production is chosen among those
+/// sharing the winning context according to the ruleset's selection policy.
+pub struct ContextRules<T> {
+    productions: HashMap<char, Vec<ContextRule<T>>>,
+    ignore: HashSet<char>,
+    selection: Selection,
+}
+
+impl<T> Default for ContextRules<T> {
+    fn default() -> Self {
+        ContextRules::new()
+    }
+}
+
+impl<T> ContextRules<T> {
+    /// Create a new, empty ruleset.
+    pub fn new() -> ContextRules<T> {
+        ContextRules {
+            productions: HashMap::new(),
+            ignore: HashSet::new(),
+            selection: Selection::Weighted,
+        }
+    }
+
+    /// Change the policy choosing among matching productions
+    pub fn set_selection(&mut self, selection: Selection) {
+        self.selection = selection;
+    }
+
+    /// Check that the weights of the productions competing for each context
+    /// sum to 1, which are the ones `apply` chooses between.  Only rulesets
+    /// using `Selection::Strict` are checked.
+    pub fn validate(&self) -> Result<(), WeightError<char>> {
+        if self.selection != Selection::Strict {
+            return Ok(());
+        }
+        for (&atom, rules) in self.productions.iter() {
+            for rule in rules.iter() {
+                WeightError::check(atom, rivals(rules, rule).map(|r| r.probability))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip every symbol of `symbols` when searching for context
+    pub fn ignore(&mut self, symbols: &str) {
+        self.ignore.extend(symbols.chars());
+    }
+
+    /// Add a production.  Productions with a context take precedence over
+    /// context-free ones for the same predecessor, and where several
+    /// contexts match, the one added first wins.  Productions sharing that
+    /// context are chosen between by the ruleset's selection.
+    pub fn add(&mut self, rule: ContextRule<T>) {
+        self.productions.entry(rule.predecessor).or_default().push(rule);
+    }
+
+    /// Add a production for `predecessor` between the `left` and `right`
+    /// context strings
+    pub fn set(&mut self, left: &str, predecessor: char, right: &str, successor: Vec<T>) {
+        self.set_prob(left, predecessor, right, successor, 1.0)
+    }
+
+    /// Add a production for `predecessor` between the `left` and `right`
+    /// context strings, with weight p
+    pub fn set_prob(&mut self, left: &str, predecessor: char, right: &str, successor: Vec<T>, p: f64) {
+        self.add(ContextRule {
+            left: left.chars().filter(|c| !c.is_whitespace()).collect(),
+            predecessor,
+            right: right.chars().filter(|c| !c.is_whitespace()).collect(),
+            successor,
+            probability: p,
+        })
+    }
+}
+
+impl ContextRules<char> {
+    /// Parse and add a production written as `a < B > c -> successor`,
+    /// where either context may be omitted
+    pub fn add_str(&mut self, src: &str) -> Result<(), ContextRuleError> {
+        self.add_str_prob(src, 1.0)
+    }
+
+    /// Parse and add a production with weight p
+    pub fn add_str_prob(&mut self, src: &str, p: f64) -> Result<(), ContextRuleError> {
+        let error = |message: &str| ContextRuleError { message: format!("{} in `{}`", message, src) };
+        let (head, successor) = src.split_once("->").ok_or_else(|| error("expected `->`"))?;
+        let (left, rest) = match head.split_once('<') {
+            Some((l, r)) => (l, r),
+            None => ("", head),
+        };
+        let (predecessor, right) = match rest.split_once('>') {
+            Some((p, r)) => (p, r),
+            None => (rest, ""),
+        };
+        let mut predecessor = predecessor.trim().chars();
+        let symbol = predecessor.next().ok_or_else(|| error("expected a predecessor"))?;
+        if predecessor.next().is_some() {
+            return Err(error("the predecessor must be a single symbol"));
+        }
+        let successor = successor.chars().filter(|c| !c.is_whitespace()).collect();
+        self.set_prob(left, symbol, right, successor, p);
+        Ok(())
+    }
+}
+
+/// the productions among `rules` sharing the context of `rule`, which are
+/// chosen between whenever that context is the one applied
+fn rivals<'a, T>(rules: &'a [ContextRule<T>], rule: &'a ContextRule<T>) -> impl Iterator<Item = &'a ContextRule<T>> {
+    rules.iter().filter(move |r| r.left == rule.left && r.right == rule.right)
+}
+
+impl<T> ContextRules<T> where T: Symbol {
+    fn left_matches(&self, state: &[T], index: usize, context: &[char]) -> bool {
+        let mut i = index;
+        for expected in context.iter().rev() {
+            loop {
+                if i == 0 {
+                    return false;
+                }
+                i -= 1;
+                match state[i].symbol() {
+                    // a completed sibling branch is not on the path to the root
+                    ']' => {
+                        let mut depth = 1;
+                        while depth > 0 {
+                            if i == 0 {
+                                return false;
+                            }
+                            i -= 1;
+                            match state[i].symbol() {
+                                ']' => depth += 1,
+                                '[' => depth -= 1,
+                                _ => {}
+                            }
+                        }
+                    }
+                    // leaving the current branch continues with its parent
+                    '[' => {}
+                    s if self.ignore.contains(&s) => {}
+                    s => {
+                        if s != *expected {
+                            return false;
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    fn right_matches(&self, state: &[T], index: usize, context: &[char]) -> bool {
+        let mut i = index + 1;
+        for expected in context.iter() {
+            if *expected == ']' {
+                // the context closes its branch: skip whatever remains of
+                // the current branch in the state
+                let mut depth = 0;
+                loop {
+                    match state.get(i).map(|a| a.symbol()) {
+                        None => return false,
+                        Some('[') => depth += 1,
+                        Some(']') if depth == 0 => break,
+                        Some(']') => depth -= 1,
+                        Some(_) => {}
+                    }
+                    i += 1;
+                }
+                i += 1;
+                continue;
+            }
+            loop {
+                let s = match state.get(i) {
+                    Some(a) => a.symbol(),
+                    None => return false,
+                };
+                if s == '[' && *expected != '[' {
+                    // lateral branches are skipped unless the context names them
+                    let mut depth = 0;
+                    loop {
+                        match state.get(i).map(|a| a.symbol()) {
+                            None => return false,
+                            Some('[') => depth += 1,
+                            Some(']') => {
+                                depth -= 1;
+                                if depth == 0 {
+                                    break;
+                                }
+                            }
+                            Some(_) => {}
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                } else if s == ']' || (s != *expected && !self.ignore.contains(&s)) {
+                    return false;
+                } else if s == *expected {
+                    i += 1;
+                    break;
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        true
+    }
+
+    /// choose among the productions of the most specific context that
+    /// `matches`, falling back to the context-free ones
+    fn apply<M>(&self, rules: &[ContextRule<T>], matches: M, input: &T, rng: &mut dyn RngCore) -> Option<Vec<T>>
+        where M: Fn(&ContextRule<T>) -> bool, T: Clone {
+        let chosen = rules.iter().find(|r| !r.is_context_free() && matches(r))
+            .or_else(|| rules.iter().find(|r| r.is_context_free()))?;
+        let candidates: Vec<&ContextRule<T>> = rivals(rules, chosen).collect();
+        let weights: Vec<f64> = candidates.iter().map(|r| r.probability).collect();
+        match self.selection.choose(&weights, rng) {
+            Some(i) => Some(candidates[i].successor.clone()),
+            None => Some(vec![input.clone()]),
+        }
+    }
+}
+
+impl<T> LRules<T> for ContextRules<T> where T: Symbol + Clone {
+    /// without its neighbours only context-free productions can apply
+    fn map(&self, input: &T, rng: &mut dyn RngCore) -> Option<Vec<T>> {
+        let rules = self.productions.get(&input.symbol())?;
+        self.apply(rules, |_| false, input, rng)
+    }
+
+    fn map_in_context(&self, state: &[T], index: usize, rng: &mut dyn RngCore) -> Option<Vec<T>> {
+        let input = &state[index];
+        let rules = self.productions.get(&input.symbol())?;
+        let matches = |r: &ContextRule<T>| {
+            self.left_matches(state, index, &r.left) && self.right_matches(state, index, &r.right)
+        };
+        self.apply(rules, matches, input, rng)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsystem::{to_string, LSystem};
+
+    #[test]
+    fn propagates_a_signal() {
+        let mut rules = ContextRules::new();
+        rules.add_str("b < a -> b").unwrap();
+        rules.add_str("b -> a").unwrap();
+        let mut system = LSystem::new_seeded(rules, "baaa".chars().collect(), 0);
+        assert_eq!(to_string(&system.next().unwrap()), "abaa");
+        assert_eq!(to_string(&system.next().unwrap()), "aaba");
+        assert_eq!(to_string(&system.next().unwrap()), "aaab");
+    }
+
+    #[test]
+    fn finds_context_through_brackets() {
+        let rules: ContextRules<char> = {
+            let mut r = ContextRules::new();
+            r.ignore("+-");
+            r
+        };
+        let state: Vec<char> = "A[+B]C[-D]E".chars().collect();
+        // B sees A, C sees A past [+B], D and E both see C
+        assert!(rules.left_matches(&state, 3, &['A']));
+        assert!(rules.left_matches(&state, 5, &['A']));
+        assert!(rules.left_matches(&state, 8, &['C']));
+        assert!(rules.left_matches(&state, 10, &['C']));
+        assert!(rules.left_matches(&state, 10, &['A', 'C']));
+        // right contexts skip lateral branches unless they are named
+        assert!(rules.right_matches(&state, 0, &['C']));
+        assert!(rules.right_matches(&state, 0, &['[', 'B', ']', 'C']));
+        assert!(rules.right_matches(&state, 5, &['E']));
+        assert!(!rules.right_matches(&state, 3, &['C']));
+    }
+
+    #[test]
+    fn validates_strict_weights_per_context() {
+        let mut rules: ContextRules<char> = ContextRules::new();
+        rules.set_selection(Selection::Strict);
+        rules.add_str_prob("b < a -> b", 0.5).unwrap();
+        rules.add_str_prob("b < a -> c", 0.5).unwrap();
+        rules.add_str("a -> a").unwrap();
+        assert_eq!(rules.validate(), Ok(()));
+        // a second context is never pooled with the first, since only one
+        // of them is ever chosen from
+        rules.add_str_prob("c < a -> c", 1.0).unwrap();
+        assert_eq!(rules.validate(), Ok(()));
+        rules.add_str_prob("a -> d", 0.5).unwrap();
+        assert_eq!(rules.validate(), Err(WeightError { atom: 'a', total: 1.5 }));
+    }
+
+    #[test]
+    fn context_takes_precedence_over_context_free_rules() {
+        for seed in 0..200 {
+            let mut rules = ContextRules::new();
+            rules.add_str("b < a -> b").unwrap();
+            rules.add_str("a -> a").unwrap();
+            rules.add_str("b -> a").unwrap();
+            let mut system = LSystem::new_seeded(rules, "ba".chars().collect(), seed);
+            assert_eq!(to_string(&system.next().unwrap()), "ab", "seed {}", seed);
+        }
+        // without the context, the context-free rule still applies
+        let mut rules = ContextRules::new();
+        rules.add_str("b < a -> b").unwrap();
+        rules.add_str("a -> c").unwrap();
+        let mut system = LSystem::new_seeded(rules, "aa".chars().collect(), 0);
+        assert_eq!(to_string(&system.next().unwrap()), "cc");
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fmt;
+use crate::lsystem::*;
+use crate::scene;
+
+
+/// An error raised while parsing a grammar, pointing at the offending 1-based
+/// line and column of the source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GrammarError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+
+/// An L-system described by the small text grammar accepted by
+/// `Grammar::parse`, together with the turtle settings used to draw it.
+#[derive(Debug)]
+pub struct Grammar {
+    pub axiom: Vec<char>,
+    pub rules: MapRules<char>,
+    /// turning angle in degrees
+    pub angle: f64,
+    /// distance moved by each `F`
+    pub step: f64,
+    pub iterations: u8,
+    /// how much each `!` narrows the lines after it
+    pub width_decrement: f64,
+    /// the exponent of the pipe model giving each line its width, if any
+    pub pipe_model: Option<f64>,
+    /// the colours picked by `'`, as in `Style::palette`
+    pub palette: Vec<String>,
+    /// the pull bending every branch, if any
+    pub tropism: Option<Tropism<Position2D>>,
+    /// what each symbol does when the plant is drawn
+    pub symbols: SymbolTable,
+}
+
+impl Grammar {
+    /// Parse a grammar.  Statements are separated by newlines or `;`, and
+    /// `#` starts a comment running to the end of the line:
+    ///
+    /// ```text
+    /// axiom: X; angle: 25; step: 5; iterations: 5
+    /// X -> F+[[X]-X]-F[-FX]+X : 0.9
+    /// F -> FF
+    /// ```
+    ///
+    /// A production is `predecessor -> successor`, optionally followed by
+    /// `: weight`, any number from 0 up.  `selection: weighted | strict | first-match` picks how
+    /// alternatives are chosen (see `Selection`): by default a production
+    /// is picked in proportion to its weight, so a lone `X -> FX : 0.5`
+    /// always applies, and with `strict` the weights of each predecessor
+    /// must sum to 1.  `width-decrement`
+    /// sets how much `!` narrows lines, and `pipe-model: 2` tapers them by
+    /// the pipe model with that exponent instead.  `palette: 4F7942 8B5A2B`
+    /// lists the colours picked by `'`; hex colours are written without the
+    /// `#`, which would start a comment.  Every setting but the axiom has a
+    /// default matching `plant_2d`.  `tropism: 0 1 0.2` bends branches
+    /// towards the direction (0, 1), which is down, with susceptibility 0.2.
+    /// `markers: B bud, R fruit` adds symbols placing markers of those
+    /// kinds to the flowers placed by `M`, and `bind: G forward, A ignore`
+    /// gives symbols any drawing action (see `Action::parse`).
+    pub fn parse(src: &str) -> Result<Grammar, GrammarError> {
+        let mut axiom = None;
+        // the line and column where each predecessor was first defined, for
+        // weight errors
+        let mut defined = HashMap::new();
+        let mut grammar = Grammar {
+            axiom: vec![],
+            rules: MapRules::new(),
+            angle: 30.0,
+            step: 5.0,
+            iterations: 5,
+            width_decrement: 0.2,
+            pipe_model: None,
+            palette: vec![],
+            tropism: None,
+            symbols: SymbolTable::new_2d(),
+        };
+
+        for (line_index, line) in src.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut column = 1;
+            for statement in line.split(';') {
+                let stmt = Statement { text: statement, line: line_index + 1, column };
+                column += statement.chars().count() + 1;
+                if statement.trim().is_empty() {
+                    continue;
+                }
+                if statement.contains("->") {
+                    let symbol = grammar.production(&stmt)?;
+                    defined.entry(symbol).or_insert_with(|| stmt.position(0));
+                    continue;
+                }
+                let (key, value) = statement.split_once(':')
+                    .ok_or_else(|| stmt.error(0, "expected `key: value` or a production `A -> B`"))?;
+                let value_offset = key.chars().count() + 1;
+                match key.trim() {
+                    "axiom" => {
+                        let symbols: Vec<char> = value.chars().filter(|c| !c.is_whitespace()).collect();
+                        if symbols.is_empty() {
+                            return Err(stmt.error(value_offset, "the axiom must not be empty"));
+                        }
+                        axiom = Some(symbols);
+                    }
+                    "angle" => grammar.angle = stmt.number(value, value_offset)?,
+                    "step" => grammar.step = stmt.number(value, value_offset)?,
+                    "width-decrement" => grammar.width_decrement = stmt.number(value, value_offset)?,
+                    "pipe-model" => {
+                        let exponent = stmt.number(value, value_offset)?;
+                        if exponent <= 0.0 {
+                            return Err(stmt.error(value_offset, "the pipe model exponent must be positive"));
+                        }
+                        grammar.pipe_model = Some(exponent);
+                    }
+                    "palette" => grammar.palette = scene::parse_palette(value),
+                    "markers" => {
+                        grammar.symbols.bind_markers(value)
+                            .map_err(|message| stmt.error(value_offset, message))?;
+                    }
+                    "bind" => {
+                        grammar.symbols.bind_all(value)
+                            .map_err(|message| stmt.error(value_offset, message))?;
+                    }
+                    "tropism" => {
+                        // errors point at the number that couldn't be read
+                        let mut numbers = vec![];
+                        let mut offset = value_offset;
+                        for part in value.split(char::is_whitespace) {
+                            if !part.is_empty() {
+                                numbers.push(stmt.number(part, offset)?);
+                            }
+                            offset += part.chars().count() + 1;
+                        }
+                        match numbers[..] {
+                            [x, y, susceptibility] => grammar.tropism = Some(Tropism {
+                                direction: Position2D { x, y },
+                                susceptibility,
+                            }),
+                            _ => return Err(stmt.error(value_offset, "expected a direction and a susceptibility, such as `0 1 0.2`")),
+                        }
+                    }
+                    "iterations" => {
+                        grammar.iterations = value.trim().parse()
+                            .map_err(|_| stmt.error(value_offset, "expected a number of iterations between 0 and 255"))?;
+                    }
+                    "selection" => {
+                        let selection = match value.trim() {
+                            "weighted" => Selection::Weighted,
+                            "strict" => Selection::Strict,
+                            "first-match" => Selection::FirstMatch,
+                            _ => return Err(stmt.error(value_offset, "expected `weighted`, `strict` or `first-match`")),
+                        };
+                        grammar.rules.set_selection(selection);
+                    }
+                    other => {
+                        return Err(stmt.error(0, format!("unknown setting `{}`", other)));
+                    }
+                }
+            }
+        }
+
+        grammar.axiom = axiom.ok_or(GrammarError {
+            line: 1,
+            column: 1,
+            message: "missing `axiom`".to_string(),
+        })?;
+        if let Err(e) = grammar.rules.validate() {
+            let (line, column) = defined.get(&e.atom).copied().unwrap_or((1, 1));
+            return Err(GrammarError { line, column, message: e.to_string() });
+        }
+        Ok(grammar)
+    }
+
+    fn production(&mut self, stmt: &Statement) -> Result<char, GrammarError> {
+        let (head, body) = stmt.text.split_once("->").unwrap();
+        let body_offset = head.chars().count() + 2;
+
+        let mut predecessor = head.trim().chars();
+        let symbol = match (predecessor.next(), predecessor.next()) {
+            (Some(c), None) => c,
+            (None, _) => return Err(stmt.error(0, "expected a predecessor before `->`")),
+            (Some(_), Some(_)) => return Err(stmt.error(0, "the predecessor must be a single symbol")),
+        };
+
+        let (successor, weight) = match body.rsplit_once(':') {
+            Some((successor, p)) => {
+                let p_offset = body_offset + successor.chars().count() + 1;
+                let p = stmt.number(p, p_offset)?;
+                if !p.is_finite() || p < 0.0 {
+                    return Err(stmt.error(p_offset, "weights must be finite and not negative"));
+                }
+                (successor, p)
+            }
+            None => (body, 1.0),
+        };
+
+        let successor: Vec<char> = successor.chars().filter(|c| !c.is_whitespace()).collect();
+        self.rules.set_prob(symbol, successor, weight);
+        Ok(symbol)
+    }
+
+    /// Create an L-system deriving from this grammar's axiom
+    pub fn lsystem(&self, seed: u64) -> LSystem<char, MapRules<char>> {
+        LSystem::new_seeded(self.rules.clone(), self.axiom.clone(), seed)
+    }
+
+    /// Create a turtle at the origin heading up the canvas, bent by this
+    /// grammar's tropism
+    pub fn turtle(&self) -> Turtle2D {
+        let mut turtle = Turtle2D::new_from(0.0, 0.0, -PI / 2.0);
+        turtle.set_tropism(self.tropism);
+        turtle
+    }
+
+    /// Create a drawer using this grammar's step and angle
+    pub fn drawer(&self) -> PlantDrawer2D<Turtle2D> {
+        PlantDrawer2D {
+            move_distance: self.step,
+            move_bearing: Bearing2D { rotation: self.angle * PI / 180.0 },
+            width_decrement: self.width_decrement,
+            symbols: self.symbols.clone(),
+        }
+    }
+}
+
+
+struct Statement<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl Statement<'_> {
+    /// the line and column `offset` characters into the statement, past
+    /// any whitespace there
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let skipped = self.text.chars().skip(offset).take_while(|c| c.is_whitespace()).count();
+        (self.line, self.column + offset + skipped)
+    }
+
+    /// an error at `position(offset)`
+    fn error(&self, offset: usize, message: impl Into<String>) -> GrammarError {
+        let (line, column) = self.position(offset);
+        GrammarError { line, column, message: message.into() }
+    }
+
+    fn number(&self, value: &str, offset: usize) -> Result<f64, GrammarError> {
+        value.trim().parse::<f64>()
+            .map_err(|_| self.error(offset, format!("expected a number, found `{}`", value.trim())))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_grammar() {
+        let grammar = Grammar::parse(
+            "axiom: X; angle: 25  # degrees\nX -> F+[[X]-X]-F[-FX]+X : 0.9\n\nF -> FF\niterations: 3"
+        ).unwrap();
+        assert_eq!(grammar.axiom, vec!['X']);
+        assert_eq!(grammar.angle, 25.0);
+        assert_eq!(grammar.step, 5.0);
+        assert_eq!(grammar.iterations, 3);
+        let mut system = grammar.lsystem(0);
+        system.next();
+        assert!(system.state().len() > 1);
+    }
+
+    #[test]
+    fn reports_line_and_column() {
+        let err = Grammar::parse("axiom: X\nX -> FX : often").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 11));
+
+        let err = Grammar::parse("axiom: X; colour: red").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 11));
+
+        let err = Grammar::parse("X -> FX").unwrap_err();
+        assert_eq!(err.message, "missing `axiom`");
+
+        let err = Grammar::parse("axiom: X\nselection: strict\n  X -> FX : 0.5\nX -> X : 0.4").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 3));
+        assert_eq!(err.message, "the production weights of 'X' sum to 0.9 instead of 1");
+    }
+
+    #[test]
+    fn parses_a_palette() {
+        let grammar = Grammar::parse("axiom: F\npalette: 4F7942 orange").unwrap();
+        assert_eq!(grammar.palette, vec!["#4F7942", "orange"]);
+    }
+
+    #[test]
+    fn parses_a_tropism() {
+        assert_eq!(Grammar::parse("axiom: F").unwrap().tropism, None);
+        let bent = Grammar::parse("axiom: F; tropism: 0 1 0.2").unwrap();
+        assert_eq!(bent.tropism, Some(Tropism { direction: Position2D { x: 0.0, y: 1.0 }, susceptibility: 0.2 }));
+        let err = Grammar::parse("axiom: X; tropism: 0 1").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 20));
+    }
+
+    #[test]
+    fn parses_bindings() {
+        let bound = Grammar::parse("axiom: A; bind: A forward, F move").unwrap();
+        assert_eq!((bound.symbols.action('A'), bound.symbols.action('F')), (Some(Action::Forward), Some(Action::Move)));
+        let err = Grammar::parse("axiom: X; bind: A fly").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 17));
+    }
+
+    #[test]
+    fn points_at_the_bad_tropism_number() {
+        let err = Grammar::parse("axiom: X; tropism: 0 x 0.2").unwrap_err();
+        assert_eq!((err.line, err.column, err.message.as_str()), (1, 22, "expected a number, found `x`"));
+        let err = Grammar::parse("axiom: X\ntropism:  0  1  high").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 17));
+    }
+
+    #[test]
+    fn weights_are_relative() {
+        let grammar = Grammar::parse("axiom: X\nX -> F : 2\nX -> X : 0.5").unwrap();
+        let mut system = grammar.lsystem(0);
+        system.next();
+        assert_eq!(system.state().len(), 1);
+
+        let err = Grammar::parse("axiom: X\nX -> F : -1").unwrap_err();
+        assert_eq!((err.line, err.column, err.message.as_str()), (2, 10, "weights must be finite and not negative"));
+        assert!(Grammar::parse("axiom: X\nX -> F : inf").is_err());
+        // strict selection still wants each predecessor's weights to sum to 1
+        assert!(Grammar::parse("axiom: X; selection: strict\nX -> F : 2").is_err());
+    }
+}
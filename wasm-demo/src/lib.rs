@@ -1,13 +1,30 @@
 mod utils;
-mod lsystem;
+pub mod lsystem;
+pub mod parametric;
+pub mod context;
+pub mod grammar;
+pub mod camera;
+pub mod svg;
+pub mod render;
+pub mod scene;
+pub mod shapes;
+pub mod raster;
+pub mod animation;
+pub mod options;
+pub mod presets;
 
-use std::f64::consts::PI;
-use rand::random;
-use std::cell::RefCell;
+use rand::prelude::*;
+use rand::rngs::StdRng;
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use lsystem::*;
+use render::CanvasRenderer;
+use animation::{Growth, GrowthOrder, PlantAnimation};
+use grammar::{Grammar, GrammarError};
+use options::PlantOptions;
+use scene::{MarkerPainters, Style};
+use camera::Camera;
 use crate::utils::*;
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
@@ -30,7 +47,7 @@ macro_rules! console_log {
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
 }
 
-fn draw_scene_2d(lines: Rc<Vec<Line2D>>, markers: Rc<Vec<Position2D>>, element: &str) -> Result<(), JsValue> {
+fn canvas_context(element: &str) -> web_sys::CanvasRenderingContext2d {
     let document = document();
     let canvas = document.get_element_by_id(element).unwrap();
     let canvas: web_sys::HtmlCanvasElement = canvas
@@ -38,158 +55,224 @@ fn draw_scene_2d(lines: Rc<Vec<Line2D>>, markers: Rc<Vec<Position2D>>, element:
         .map_err(|_| ())
         .unwrap();
 
-    let ctx = canvas
+    canvas
         .get_context("2d")
         .unwrap()
         .unwrap()
         .dyn_into::<web_sys::CanvasRenderingContext2d>()
-        .unwrap();
+        .unwrap()
+}
 
-    let height = f64::from(ctx.canvas().unwrap().height());
-    let width = f64::from(ctx.canvas().unwrap().width());
-
-    // draw background and floor
-    ctx.set_fill_style(&JsValue::from_str("#d7fcff"));
-    ctx.fill_rect(0.0, 0.0, width, height);
-    ctx.set_fill_style(&JsValue::from_str("#4A3728"));
-    ctx.fill_rect(0.0, height - 5.0, width, height);
-
-    let x_offset = width / 2.0;
-    let y_offset = height - 50.0;
-
-    // draw pot
-    ctx.set_stroke_style(&JsValue::from_str("#B35642"));
-    ctx.set_fill_style(&JsValue::from_str("#B35642"));
-    ctx.fill_rect(x_offset - 30.0, y_offset, 60.0, 10.0);
-    ctx.begin_path();
-    ctx.move_to(x_offset - 20.0, y_offset + 10.0);
-    ctx.line_to(x_offset + 25.0, y_offset + 10.0);
-    ctx.line_to(x_offset + 15.0, height - 5.0);
-    ctx.line_to(x_offset - 15.0, height - 5.0);
-    ctx.line_to(x_offset - 25.0, y_offset + 10.0);
-    ctx.close_path();
-    ctx.fill();
-    ctx.stroke();
-
-    ctx.set_stroke_style(&JsValue::from_str("#4F7942"));
-    ctx.set_line_width(3.0);
-    ctx.begin_path();
-
-    // Here we want to call `requestAnimationFrame` in a loop, but only a fixed
-    // number of times. After it's done we want all our resources cleaned up. To
-    // achieve this we're using an `Rc`. The `Rc` will eventually store the
-    // closure we want to execute on each frame, but to start out it contains
-    // `None`.
-    //
-    // After the `Rc` is made we'll actually create the closure, and the closure
-    // will reference one of the `Rc` instances. The other `Rc` reference is
-    // used to store the closure, request the first frame, and then is dropped
-    // by this function.
-    //
-    // Inside the closure we've got a persistent `Rc` reference, which we use
-    // for all future iterations of the loop
-    let ctx_ref = Rc::new(ctx);
-    let f = Rc::new(RefCell::new(None));
-    let lines_ref = lines.clone();
-    let markers_ref = markers.clone();
-    let g = f.clone();
-
-    let mut i = 0;
-    let mut j = 0;
-    let mut prev_time = window().performance().unwrap().now();
-
-    *g.borrow_mut() = Some(Closure::wrap(Box::new(move || {
-        if i < lines_ref.len() {
-            // we want to draw at the bottom of the canvas
-            let start_x = lines_ref[i].start.x + x_offset;
-            let start_y = lines_ref[i].start.y + y_offset;
-            let end_x = lines_ref[i].end.x + x_offset;
-            let end_y = lines_ref[i].end.y + y_offset;
-            ctx_ref.move_to(start_x, start_y);
-            ctx_ref.line_to(end_x, end_y);
-            ctx_ref.stroke();
-
-            i += 1;
-
-            // Schedule ourself for another requestAnimationFrame callback.
-            request_animation_frame(f.borrow().as_ref().unwrap());
-        } else if j < markers_ref.len() {
-            ctx_ref.close_path();
-
-            // if we are done with lines, draw flowers
-            // otherwise they don't pop in nicely
-            if window().performance().unwrap().now() - prev_time > 200.0 {
-                let start_x = markers_ref[j].x + x_offset;
-                let start_y = markers_ref[j].y + y_offset;
-
-                ctx_ref.set_fill_style(&JsValue::from_str("#C8A2C8"));
-                ctx_ref.begin_path();
-                for i in 0..5 {
-                    let theta1 = PI * 2.0 / 5.0 * f64::from(i + 1);
-                    let theta2 = PI * 2.0 / 5.0 * f64::from(i);
-                    let x1 = 20.0 * theta1.sin() + start_x;
-                    let y1 = 20.0 * theta1.cos() + start_y;
-                    let x2 = 20.0 * theta2.sin() + start_x;
-                    let y2 = 20.0 * theta2.cos() + start_y;
-                    ctx_ref.move_to(start_x, start_y);
-                    ctx_ref.bezier_curve_to(x1, y1, x2, y2, start_x, start_y);
-                }
-                ctx_ref.close_path();
-                ctx_ref.fill();
-
-                ctx_ref.set_fill_style(&JsValue::from_str("#FDDA0D"));
-                ctx_ref.begin_path();
-                ctx_ref.arc(start_x, start_y, 3.0, 0.0, 2.0 * PI).unwrap();
-                ctx_ref.close_path();
-                ctx_ref.fill();
-
-                j += 1;
-                prev_time = window().performance().unwrap().now();
-            }
-
-            request_animation_frame(f.borrow().as_ref().unwrap());
-        } else {
-            // Drop our handle to this closure so that it will get cleaned
-            // up once we return.
-            let _ = f.borrow_mut().take();
-            return;
-        }
-    }) as Box<dyn FnMut()>));
-
-    request_animation_frame(g.borrow().as_ref().unwrap());
+/// start growing a plant on the canvas `element`, replacing any plant
+/// already growing there
+fn draw_scene_2d(style: Style, painters: MarkerPainters, plant: Drawing2D, element: &str, seed: u32) -> PlantAnimation {
+    let renderer = CanvasRenderer::new(canvas_context(element));
+    let (width, height) = renderer.size();
+    let mut growth = Growth::new(renderer, style, Rc::new(plant), width, height);
+    growth.set_painters(painters);
+    PlantAnimation::start(growth, element, seed)
+}
 
+/// draw a projected plant in one pass, from the farthest to the nearest
+fn draw_scene_3d(plant: &Drawing3D, camera: &Camera, element: &str) -> Result<(), JsValue> {
+    let mut renderer = CanvasRenderer::new(canvas_context(element));
+    let (width, height) = renderer.size();
+    // 3D plants have always been drawn a little thinner than 2D ones
+    let style = Style { line_width: 2.0, ..Style::default() };
+    scene::draw_plant_3d(&mut renderer, &style, &MarkerPainters::default(), plant, camera, width, height);
     Ok(())
 }
 
-#[wasm_bindgen]
-pub fn plant_2d(rounds: u8, element: &str) -> Result<(), JsValue> {
-    set_panic_hook();
-    let mut rules: MapRules<char> = MapRules::new();
-    rules.set_str_prob('X', "F+[[X]-X]-F[-FX]+X", 0.9);
-    // only applies if previous rule does not trigger
-    rules.set_str_prob('X', "M", 0.25);
-    rules.set_str('F', "FF");
-    let axiom = "X".chars().collect();
-    let mut system = LSystem::new(rules, axiom);
-
-    for _ in 0..rounds-1 {
-        system.next();
+/// derive the default plant for `rounds` generations and trace it with a 2D
+/// turtle.  Every random choice is drawn from `seed`, so the same seed always
+/// produces identical lines and markers.
+pub fn generate_plant_2d(seed: u64, rounds: u8) -> (Vec<Line2D>, Vec<Marker2D>) {
+    let plant = generate_plant_2d_with_options(&PlantOptions::new(), seed, rounds)
+        .expect("the default options are valid");
+    (plant.lines, plant.markers)
+}
+
+/// derive the plant described by `options` for `rounds` generations and
+/// trace it with a 2D turtle, describing where each line sits in the plant.
+/// Every random choice is drawn from `seed`.
+pub fn generate_plant_2d_with_options(options: &PlantOptions, seed: u64, rounds: u8) -> Result<Drawing2D, GrammarError> {
+    let grammar = options.grammar()?;
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut system = grammar.lsystem(rng.gen());
+    system.nth_generation(usize::from(rounds));
+
+    let mut turtle = options.turtle();
+    let drawer = options.drawer(&grammar, rng.gen(), rng.gen());
+    let mut plant = drawer.draw_born(system.state(), system.born(), &mut turtle, &mut rng);
+    if options.pipe_model {
+        lsystem::pipe_model(&mut plant.info, options.pipe_exponent);
+    }
+    Ok(plant)
+}
+
+/// derive the plant described by `grammar` for `rounds` generations and
+/// trace it with a 2D turtle.  Every random choice is drawn from `seed`.
+pub fn generate_plant_from_grammar(grammar: &Grammar, seed: u64, rounds: u8) -> Drawing2D {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut system = grammar.lsystem(rng.gen());
+    system.nth_generation(usize::from(rounds));
+
+    let mut turtle = grammar.turtle();
+    let mut plant = grammar.drawer().draw_born(system.state(), system.born(), &mut turtle, &mut rng);
+    if let Some(exponent) = grammar.pipe_model {
+        lsystem::pipe_model(&mut plant.info, exponent);
     }
+    plant
+}
 
-    let state = system.next().unwrap();
+/// derive a bush in 3D for `rounds` generations and trace it with a 3D
+/// turtle, growing up the y axis from the origin.  Every random choice is
+/// drawn from `seed`.
+pub fn generate_plant_3d(seed: u64, rounds: u8) -> Drawing3D {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut rules: MapRules<char> = MapRules::new();
+    rules.set_str_prob('A', "[&FA]/////[&FA]///////[&FA]", 0.85);
+    rules.set_str_prob('A', "M", 0.15);
+    rules.set_str('F', "S/////F");
+    rules.set_str('S', "F");
+    let axiom = "FA".chars().collect();
+    let mut system = LSystem::new_seeded(rules, axiom, rng.gen());
+    system.nth_generation(usize::from(rounds));
 
-    let mut turtle = Turtle2D::new_from(0.0, 0.0, -PI / 2.0);
-    let drawer = PlantDrawer2D::<Turtle2D>{
-        move_distance: 5.0 + (random::<f64>() * 2.0 - 1.0),
-        move_bearing: Bearing2D{
-            rotation: PI / 6.0 + ((random::<f64>() * 2.0 - 1.0) / (2.0 * PI))
-        }
+    let mut turtle = Turtle3D::new();
+    let drawer = PlantDrawer3D {
+        move_distance: 4.0,
+        angle: 22.5_f64.to_radians(),
+        width_decrement: 0.2,
+        symbols: SymbolTable::new_3d(),
     };
 
-    let (lines, markers) = drawer.map(&state, &mut turtle);
+    drawer.draw_born(system.state(), system.born(), &mut turtle, &mut rng)
+}
+
+/// draw a 3D plant seen through `camera` from a fresh random seed, returning
+/// the seed
+#[wasm_bindgen]
+pub fn plant_3d(rounds: u8, element: &str, camera: &Camera) -> Result<u32, JsValue> {
+    set_panic_hook();
+    let seed: u32 = random();
+    let plant = generate_plant_3d(u64::from(seed), rounds);
+    draw_scene_3d(&plant, camera, element)?;
+    Ok(seed)
+}
+
+/// grow a plant from a fresh random seed.  The returned animation exposes
+/// the seed so the page can share or regenerate it with `plant_2d_seeded`.
+#[wasm_bindgen]
+pub fn plant_2d(rounds: u8, element: &str) -> PlantAnimation {
+    plant_2d_seeded(random(), rounds, element)
+}
+
+/// grow the plant identified by `seed`
+#[wasm_bindgen]
+pub fn plant_2d_seeded(seed: u32, rounds: u8, element: &str) -> PlantAnimation {
+    plant_2d_grown(seed, rounds, element, GrowthOrder::Drawing)
+}
+
+/// grow the plant identified by `seed`, drawing its lines in `order`
+#[wasm_bindgen]
+pub fn plant_2d_grown(seed: u32, rounds: u8, element: &str, order: GrowthOrder) -> PlantAnimation {
+    let options = PlantOptions { order, ..PlantOptions::new() };
+    grow_plant_2d(&options, seed, rounds, element).expect("the default options are valid")
+}
+
+/// grow the plant identified by `seed` as described by `options`.  Errors in
+/// the options' rules are returned as `line L, column C: message` strings.
+#[wasm_bindgen]
+pub fn plant_2d_with_options(seed: u32, rounds: u8, element: &str, options: &PlantOptions) -> Result<PlantAnimation, JsValue> {
+    grow_plant_2d(options, seed, rounds, element).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+fn grow_plant_2d(options: &PlantOptions, seed: u32, rounds: u8, element: &str) -> Result<PlantAnimation, GrammarError> {
+    set_panic_hook();
+    let plant = generate_plant_2d_with_options(options, u64::from(seed), rounds)?;
+    let plant = animation::order_lines(&plant, options.order);
+
+    console_log!("{:?}", plant.lines);
+    console_log!("{:?}", plant.markers);
+
+    Ok(draw_scene_2d(options.style(), options.painters(), plant, element, seed))
+}
+
+/// render the plant identified by `seed` as an SVG document
+#[wasm_bindgen]
+pub fn plant_2d_svg(seed: u32, rounds: u8) -> String {
+    let plant = generate_plant_2d_with_options(&PlantOptions::new(), u64::from(seed), rounds)
+        .expect("the default options are valid");
+    svg::plant_svg(&Style::default(), &plant)
+}
+
+/// render the plant identified by `seed` and described by `options` as an
+/// SVG document
+#[wasm_bindgen]
+pub fn plant_2d_svg_with_options(seed: u32, rounds: u8, options: &PlantOptions) -> Result<String, JsValue> {
+    let plant = generate_plant_2d_with_options(options, u64::from(seed), rounds)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok(svg::plant_svg_with(&options.style(), &options.painters(), &plant))
+}
+
+/// grow the plant described by the grammar source `src` from a fresh random
+/// seed.  Parse errors are returned as `line L, column C: message` strings.
+#[wasm_bindgen]
+pub fn plant_from_grammar(src: &str, element: &str) -> Result<PlantAnimation, JsValue> {
+    set_panic_hook();
+    let grammar = Grammar::parse(src).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let style = Style { palette: grammar.palette.clone(), ..Style::default() };
+    Ok(grow_grammar(&grammar, style, element))
+}
+
+/// the names of the built-in presets, for `draw_preset`
+#[wasm_bindgen]
+pub fn preset_names() -> Vec<String> {
+    presets::PRESETS.iter().map(|p| p.name.to_string()).collect()
+}
+
+/// grow the preset called `name` from a fresh random seed in the preset's
+/// style
+#[wasm_bindgen]
+pub fn draw_preset(name: &str, element: &str) -> Result<PlantAnimation, JsValue> {
+    set_panic_hook();
+    let preset = presets::preset(name).ok_or_else(|| JsValue::from_str(&format!("unknown preset `{}`", name)))?;
+    Ok(grow_grammar(&preset.grammar(), preset.style(), element))
+}
+
+fn grow_grammar(grammar: &Grammar, style: Style, element: &str) -> PlantAnimation {
+    let seed: u32 = random();
+    let plant = generate_plant_from_grammar(grammar, u64::from(seed), grammar.iterations);
+    draw_scene_2d(style, MarkerPainters::default(), plant, element, seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_draws_same_plant() {
+        let a = generate_plant_2d(42, 4);
+        let b = generate_plant_2d(42, 4);
+        assert!(!a.0.is_empty());
+        assert_eq!(a, b);
+    }
 
-    console_log!("{:?}", lines);
-    console_log!("{:?}", markers);
+    #[test]
+    fn zero_rounds_draws_the_axiom() {
+        let (lines, markers) = generate_plant_2d(5, 0);
+        assert!(lines.is_empty());
+        assert!(markers.is_empty());
+    }
+
+    #[test]
+    fn svg_output_is_reproducible() {
+        assert_eq!(plant_2d_svg(9, 4), plant_2d_svg(9, 4));
+    }
 
-    draw_scene_2d(Rc::new(lines), Rc::new(markers), element)
+    #[test]
+    fn different_seeds_draw_different_plants() {
+        assert_ne!(generate_plant_2d(1, 4), generate_plant_2d(2, 4));
+    }
 }
@@ -1,57 +1,161 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::hash::Hash;
+use std::mem;
+use std::rc::Rc;
 use rand::prelude::*;
+use rand::rngs::StdRng;
 
 
+/// An L-System rewriting its state one generation at a time.  Each
+/// generation is built in a single pass into a spare buffer which is then
+/// swapped with the current state, so a step is linear in the length of the
+/// state and the buffers are reused from one generation to the next.
 pub struct LSystem<T, P> where P: LRules<T> {
     rules: P,
     pub axiom: Vec<T>,
-    state: Vec<T>,
+    state: Rc<Vec<T>>,
+    spare: Vec<T>,
+    /// the generation each atom of the state first appeared in
+    born: Vec<usize>,
+    spare_born: Vec<usize>,
+    generation: usize,
+    seed: u64,
+    rng: StdRng,
 }
 
 impl<T, P> LSystem<T, P> where P: LRules<T>, T: Clone {
-    /// create a new L-System from rules and an axiom
+    /// create a new L-System from rules and an axiom, seeded from entropy
     pub fn new(rules: P, axiom: Vec<T>) -> LSystem<T, P> {
+        LSystem::new_seeded(rules, axiom, random())
+    }
+
+    /// create a new L-System from rules and an axiom whose stochastic
+    /// productions are driven by `seed`, so the same seed always derives the
+    /// same states
+    pub fn new_seeded(rules: P, axiom: Vec<T>, seed: u64) -> LSystem<T, P> {
         LSystem {
-            rules: rules,
-            state: axiom.clone(),
-            axiom: axiom,
+            rules,
+            state: Rc::new(axiom.clone()),
+            spare: vec![],
+            born: vec![0; axiom.len()],
+            spare_born: vec![],
+            generation: 0,
+            axiom,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
         }
     }
 
-    /// reset the L-System state back to its axiom
+    /// the current state of the L-System
+    pub fn state(&self) -> &[T] {
+        &self.state
+    }
+
+    /// The generation each atom of the current state first appeared in.  An
+    /// atom rewritten into atoms of its own symbol passes its generation on
+    /// to them, since `F -> FF` only divides a segment that already grew;
+    /// every other atom a production writes is new.
+    pub fn born(&self) -> &[usize] {
+        &self.born
+    }
+
+    /// the seed driving this L-System's random number generator
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// reset the L-System state back to its axiom and rewind its random
+    /// number generator, so the same derivation is replayed
     pub fn reset(&mut self) {
-        self.state = self.axiom.clone();
+        self.state = Rc::new(self.axiom.clone());
+        self.born = vec![0; self.axiom.len()];
+        self.generation = 0;
+        self.rng = StdRng::seed_from_u64(self.seed);
     }
 }
 
-impl<T, P> Iterator for LSystem<T, P> where P: LRules<T>, T: Clone {
-    type Item = Vec<T>;
+impl<T, P> LSystem<T, P> where P: LRules<T>, T: Clone + PartialEq + Symbol {
+    /// the number of derivation steps applied since the axiom
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
 
-    /// Get the next iteration of the L-System by evaluating its associated
-    /// production rules on its current states.
-    fn next(&mut self) -> Option<Vec<T>> {
-        let mut i: usize = 0;
-        let mut expanded = false;
-        while i < self.state.len() {
-            let atom = self.state[i].clone();
-            let production = self.rules.map(&atom);
-            match production {
-                Some(atoms) => {
-                    self.state.remove(i);
-                    for a in atoms.into_iter() {
-                        self.state.insert(i, a);
-                        i += 1;
-                    }
-                    expanded = true;
-                }
-                None => {
-                    i += 1;
-                }
+    /// Derive the next generation, returning whether the state changed.  A
+    /// production that rewrites an atom into itself is not a change, so a
+    /// `false` result means this step was a fixed point.  The generation is
+    /// counted either way.
+    pub fn step(&mut self) -> bool {
+        // productions read their context from the previous generation, so
+        // the successor is built in the spare buffer rather than in place
+        let mut next = mem::take(&mut self.spare);
+        next.clear();
+        next.reserve(self.state.len());
+        let mut born = mem::take(&mut self.spare_born);
+        born.clear();
+        born.reserve(self.state.len());
+        let mut changed = false;
+        for i in 0..self.state.len() {
+            let start = next.len();
+            if !self.rules.rewrite_into(&self.state, i, &mut self.rng, &mut next) {
+                next.push(self.state[i].clone());
+                born.push(self.born[i]);
+                continue;
+            }
+            if !changed {
+                changed = next.len() - start != 1 || next[start] != self.state[i];
+            }
+            let symbol = self.state[i].symbol();
+            born.extend(next[start..].iter().map(|a| if a.symbol() == symbol { self.born[i] } else { self.generation + 1 }));
+        }
+        self.spare_born = mem::replace(&mut self.born, born);
+        let previous = mem::replace(&mut self.state, Rc::new(next));
+        // the previous state can only be recycled if no caller still holds it
+        if let Ok(buffer) = Rc::try_unwrap(previous) {
+            self.spare = buffer;
+        }
+        self.generation += 1;
+        changed
+    }
+
+    /// Derive the state of generation `n`, resetting to the axiom first if
+    /// the L-System is already past it.  Generation 0 is the axiom.
+    pub fn nth_generation(&mut self, n: usize) -> &[T] {
+        if n < self.generation {
+            self.reset();
+        }
+        while self.generation < n {
+            self.step();
+        }
+        &self.state
+    }
+
+    /// Step until a generation leaves the state unchanged or `max`
+    /// generations have been derived.  Returns the first generation the
+    /// state settled at, or `None` if it was still changing at `max`.  For
+    /// stochastic rules a step in which every atom happened to keep its
+    /// value also counts as stable.
+    pub fn step_until_stable(&mut self, max: usize) -> Option<usize> {
+        while self.generation < max {
+            if !self.step() {
+                return Some(self.generation - 1);
             }
         }
-        if expanded {
-            Some(self.state.clone())
+        None
+    }
+}
+
+impl<T, P> Iterator for LSystem<T, P> where P: LRules<T>, T: Clone + PartialEq + Symbol {
+    type Item = Rc<Vec<T>>;
+
+    /// Get the next iteration of the L-System by evaluating its associated
+    /// production rules on its current states, or `None` once the state has
+    /// reached a fixed point.  The state is shared rather than copied, so
+    /// holding on to it only costs the L-System the chance to reuse its
+    /// buffer.
+    fn next(&mut self) -> Option<Rc<Vec<T>>> {
+        if self.step() {
+            Some(Rc::clone(&self.state))
         } else {
             None
         }
@@ -63,40 +167,171 @@ impl<T, P> Iterator for LSystem<T, P> where P: LRules<T>, T: Clone {
 pub trait LRules<T> {
     /// perform a mapping of one atom to a string.  It returns `Some(Vec<T>)`
     /// if the atom is a variable with an existing production rule, or `None`
-    /// if the atom should be considered terminal.
-    fn map(&self, input: &T) -> Option<Vec<T>>;
+    /// if the atom should be considered terminal.  Stochastic rules must draw
+    /// all of their randomness from `rng`.
+    fn map(&self, input: &T, rng: &mut dyn RngCore) -> Option<Vec<T>>;
+
+    /// perform a mapping of the atom at `index` of `state`, which lets
+    /// context-sensitive rules inspect its neighbours.  Rules without context
+    /// only need to implement `map`.
+    fn map_in_context(&self, state: &[T], index: usize, rng: &mut dyn RngCore) -> Option<Vec<T>> {
+        self.map(&state[index], rng)
+    }
+
+    /// append the production of the atom at `index` of `state` to `out`,
+    /// returning `false` without touching `out` if the atom is terminal.
+    /// Rules able to write their productions directly can override this to
+    /// avoid building an intermediate vector per atom.
+    fn rewrite_into(&self, state: &[T], index: usize, rng: &mut dyn RngCore, out: &mut Vec<T>) -> bool {
+        match self.map_in_context(state, index, rng) {
+            Some(atoms) => {
+                out.extend(atoms);
+                true
+            }
+            None => false,
+        }
+    }
 }
 
+/// How a stochastic ruleset chooses among the productions of one atom.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Selection {
+    /// choose each production with probability proportional to its weight
+    #[default]
+    Weighted,
+    /// like `Weighted`, but the weights of each atom are expected to sum to 1;
+    /// the `validate` of each ruleset reports atoms for which they do not
+    Strict,
+    /// roll each production in turn against its own probability and apply
+    /// the first that succeeds, keeping the atom if none does.  The effective
+    /// probabilities then depend on the order of the productions.
+    FirstMatch,
+}
+
+impl Selection {
+    /// pick the index of a production given their weights, or `None` if the
+    /// atom should be kept as it is
+    pub fn choose(&self, weights: &[f64], rng: &mut dyn RngCore) -> Option<usize> {
+        match self {
+            Selection::FirstMatch => weights.iter().position(|p| rng.gen::<f64>() <= *p),
+            Selection::Weighted | Selection::Strict => {
+                let total: f64 = weights.iter().sum();
+                if total <= 0.0 {
+                    return None;
+                }
+                let mut roll = rng.gen::<f64>() * total;
+                for (i, w) in weights.iter().enumerate() {
+                    if roll < *w {
+                        return Some(i);
+                    }
+                    roll -= w;
+                }
+                // guard against rounding leaving the roll just past the end
+                weights.iter().rposition(|w| *w > 0.0)
+            }
+        }
+    }
+}
+
+/// An error reported by a ruleset's `validate` when the weights of an
+/// atom's productions do not sum to 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeightError<T> {
+    pub atom: T,
+    pub total: f64,
+}
+
+impl<T: fmt::Debug> fmt::Display for WeightError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the production weights of {:?} sum to {} instead of 1", self.atom, self.total)
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for WeightError<T> {}
+
+impl<T> WeightError<T> {
+    /// an error for `atom` unless `weights` sum to 1
+    pub fn check(atom: T, weights: impl Iterator<Item = f64>) -> Result<(), WeightError<T>> {
+        let total: f64 = weights.sum();
+        if (total - 1.0).abs() > 1e-9 {
+            return Err(WeightError { atom, total });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct MapRules<T: Hash + Eq> {
     productions: HashMap<T, Vec<(Vec<T>, f64)>>,
+    selection: Selection,
+}
+
+impl<T> Default for MapRules<T> where T: Hash + Eq + Clone {
+    fn default() -> Self {
+        MapRules::new()
+    }
 }
 
 impl<T> MapRules<T> where T: Hash + Eq + Clone {
-    /// Create a new, empty ruleset.
+    /// Create a new, empty ruleset choosing productions by weight.
     pub fn new() -> MapRules<T> {
+        MapRules::with_selection(Selection::Weighted)
+    }
+
+    /// Create a new, empty ruleset with the given selection policy.
+    pub fn with_selection(selection: Selection) -> MapRules<T> {
         MapRules {
             productions: HashMap::new(),
+            selection,
         }
     }
 
+    /// the policy choosing among the productions of an atom
+    pub fn selection(&self) -> Selection {
+        self.selection
+    }
+
+    /// Change the policy choosing among the productions of an atom
+    pub fn set_selection(&mut self, selection: Selection) {
+        self.selection = selection;
+    }
+
     /// Set an atom to produce a vector
     pub fn set(&mut self, k: T, v: Vec<T>) -> Option<Vec<T>> {
         self.set_prob(k, v, 1.0)
     }
 
-    /// Set an atom to produce a vector with probability p
+    /// Set an atom to produce a vector with weight p (see `Selection`)
     pub fn set_prob(&mut self, k: T, v: Vec<T>, p: f64) -> Option<Vec<T>> {
-        if !self.productions.contains_key(&k) {
-            self.productions.insert(k.clone(), Vec::<(Vec<T>, f64)>::new());
-        }
-        let vec = self.productions.get_mut(&k).unwrap();
-        vec.push((v.clone(), p.clone()));
+        let vec = self.productions.entry(k).or_default();
+        vec.push((v.clone(), p));
         if vec.len() == 1 {
             Some(v)
         } else {
             None
         }
     }
+
+    fn choose(&self, prods: &[(Vec<T>, f64)], rng: &mut dyn RngCore) -> Option<usize> {
+        // a single certain production is by far the most common case
+        if prods.len() == 1 && prods[0].1 >= 1.0 {
+            return Some(0);
+        }
+        let weights: Vec<f64> = prods.iter().map(|(_, p)| *p).collect();
+        self.selection.choose(&weights, rng)
+    }
+
+    /// Check that the weights of every atom sum to 1.  Only rulesets using
+    /// `Selection::Strict` are checked.
+    pub fn validate(&self) -> Result<(), WeightError<T>> {
+        if self.selection != Selection::Strict {
+            return Ok(());
+        }
+        for (atom, prods) in self.productions.iter() {
+            WeightError::check(atom.clone(), prods.iter().map(|(_, p)| *p))?;
+        }
+        Ok(())
+    }
 }
 
 impl MapRules<char> {
@@ -109,7 +344,7 @@ impl MapRules<char> {
         self.set(k, rule)
     }
 
-    /// Set an atom to produce the Vec<char> corresponding to a string with probability p
+    /// Set an atom to produce the Vec<char> corresponding to a string with weight p
     pub fn set_str_prob(&mut self, k: char, v: &str, p: f64) -> Option<Vec<char>> {
         let mut rule = Vec::new();
         for c in v.chars() {
@@ -119,25 +354,51 @@ impl MapRules<char> {
     }
 }
 
-impl<T: ?Sized> LRules<T> for MapRules<T> where T: Clone + Hash + Eq {
-    fn map(&self, input: &T) -> Option<Vec<T>> {
+impl<T> LRules<T> for MapRules<T> where T: Clone + Hash + Eq {
+    fn map(&self, input: &T, rng: &mut dyn RngCore) -> Option<Vec<T>> {
+        let prods = self.productions.get(input)?;
+        match self.choose(prods, rng) {
+            Some(i) => Some(prods[i].0.clone()),
+            None => Some(vec![input.clone()]),
+        }
+    }
+
+    fn rewrite_into(&self, state: &[T], index: usize, rng: &mut dyn RngCore, out: &mut Vec<T>) -> bool {
+        let input = &state[index];
         match self.productions.get(input) {
             Some(prods) => {
-                for (v, p) in prods.iter() {
-                    if random::<f64>() <= *p {
-                        return Some(v.clone());
-                    }
+                match self.choose(prods, rng) {
+                    Some(i) => out.extend_from_slice(&prods[i].0),
+                    None => out.push(input.clone()),
                 }
-                return Some(vec![input.clone()])
-            },
-            None => None,
+                true
+            }
+            None => false,
         }
     }
 }
 
+/// An atom a turtle can interpret: a symbol, optionally carrying numeric
+/// parameters as in the parametric module `F(2.5)`.
+pub trait Symbol {
+    /// the symbol naming this atom
+    fn symbol(&self) -> char;
+
+    /// the i-th actual parameter of this atom, if it has one
+    fn param(&self, _i: usize) -> Option<f64> {
+        None
+    }
+}
+
+impl Symbol for char {
+    fn symbol(&self) -> char {
+        *self
+    }
+}
+
 /// A convenience function to print out the String representation of a char
 /// vector.
-pub fn to_string(v: &Vec<char>) -> String {
+pub fn to_string(v: &[char]) -> String {
     let mut out = String::with_capacity(v.len());
     for c in v.iter() {
         out.push(*c);
@@ -150,61 +411,194 @@ pub trait Flippable<B> {
     fn flip(&self) -> B;
 }
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq)]
 pub struct Position2D {
     pub x: f64,
     pub y: f64,
 }
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq)]
 pub struct Line2D {
     pub start: Position2D,
     pub end: Position2D,
 }
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq)]
 pub struct Bearing2D {
     pub rotation: f64,
 }
 
 impl Flippable<Bearing2D> for Bearing2D {
     fn flip(&self) -> Bearing2D {
-        Bearing2D { rotation: -1.0 *  self.rotation.clone() }
+        Bearing2D { rotation: -self.rotation }
     }
 }
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq)]
 pub struct Position3D {
     pub x: f64,
     pub y: f64,
     pub z: f64,
 }
 
-#[derive(Clone, Debug, Copy)]
+/// Positions double as 3D vectors for the turtle's frame arithmetic.
+impl Position3D {
+    pub fn new(x: f64, y: f64, z: f64) -> Position3D {
+        Position3D { x, y, z }
+    }
+
+    pub fn add(&self, other: &Position3D) -> Position3D {
+        Position3D::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+
+    pub fn sub(&self, other: &Position3D) -> Position3D {
+        Position3D::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+
+    pub fn scale(&self, s: f64) -> Position3D {
+        Position3D::new(self.x * s, self.y * s, self.z * s)
+    }
+
+    pub fn dot(&self, other: &Position3D) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(&self, other: &Position3D) -> Position3D {
+        Position3D::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    pub fn length(&self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalized(&self) -> Position3D {
+        let length = self.length();
+        if length == 0.0 {
+            *self
+        } else {
+            self.scale(1.0 / length)
+        }
+    }
+
+    /// rotate by `angle` radians around the unit vector `axis`, turning
+    /// anticlockwise when looking down the axis
+    pub fn rotated(&self, axis: &Position3D, angle: f64) -> Position3D {
+        let (sin, cos) = angle.sin_cos();
+        self.scale(cos)
+            .add(&axis.cross(self).scale(sin))
+            .add(&axis.scale(axis.dot(self) * (1.0 - cos)))
+    }
+}
+
+#[derive(Clone, Debug, Copy, PartialEq)]
 pub struct Line3D {
     pub start: Position3D,
     pub end: Position3D,
 }
 
-#[derive(Clone, Debug, Copy)]
+/// An orientation in 3D, given as the turtle's heading, left and up vectors
+/// (the HLU frame of The Algorithmic Beauty of Plants).  As a turn, the
+/// vectors are expressed in the turtle's own frame, so the identity leaves
+/// the turtle unchanged and `Bearing3D::yaw`, `pitch` and `roll` are the
+/// rotation matrices RU, RL and RH.
+#[derive(Clone, Debug, Copy, PartialEq)]
 pub struct Bearing3D {
-    pub azimuth: f64,
-    pub declination: f64,
+    pub heading: Position3D,
+    pub left: Position3D,
+    pub up: Position3D,
+}
+
+impl Bearing3D {
+    pub fn identity() -> Bearing3D {
+        Bearing3D {
+            heading: Position3D::new(1.0, 0.0, 0.0),
+            left: Position3D::new(0.0, 1.0, 0.0),
+            up: Position3D::new(0.0, 0.0, 1.0),
+        }
+    }
+
+    /// rotate by angle radians around the up vector (RU)
+    pub fn yaw(angle: f64) -> Bearing3D {
+        let (sin, cos) = angle.sin_cos();
+        Bearing3D {
+            heading: Position3D::new(cos, -sin, 0.0),
+            left: Position3D::new(sin, cos, 0.0),
+            up: Position3D::new(0.0, 0.0, 1.0),
+        }
+    }
+
+    /// rotate by angle radians around the left vector (RL)
+    pub fn pitch(angle: f64) -> Bearing3D {
+        let (sin, cos) = angle.sin_cos();
+        Bearing3D {
+            heading: Position3D::new(cos, 0.0, sin),
+            left: Position3D::new(0.0, 1.0, 0.0),
+            up: Position3D::new(-sin, 0.0, cos),
+        }
+    }
+
+    /// rotate by angle radians around the heading vector (RH)
+    pub fn roll(angle: f64) -> Bearing3D {
+        let (sin, cos) = angle.sin_cos();
+        Bearing3D {
+            heading: Position3D::new(1.0, 0.0, 0.0),
+            left: Position3D::new(0.0, cos, sin),
+            up: Position3D::new(0.0, -sin, cos),
+        }
+    }
+
+    /// express a vector given in this frame's coordinates in the coordinates
+    /// the frame itself is given in
+    pub fn apply(&self, v: &Position3D) -> Position3D {
+        self.heading.scale(v.x).add(&self.left.scale(v.y)).add(&self.up.scale(v.z))
+    }
+
+    /// this orientation turned by `turn`, given in this orientation's frame
+    pub fn then(&self, turn: &Bearing3D) -> Bearing3D {
+        let heading = self.apply(&turn.heading).normalized();
+        let up = self.apply(&turn.up);
+        // re-orthogonalize so rounding errors don't accumulate over many turns
+        let left = up.cross(&heading).normalized();
+        Bearing3D {
+            heading,
+            left,
+            up: heading.cross(&left),
+        }
+    }
 }
 
 impl Flippable<Bearing3D> for Bearing3D {
+    /// the inverse rotation, which is the transpose of the frame
     fn flip(&self) -> Bearing3D {
-        Bearing3D { azimuth: -1.0 *  self.azimuth.clone(), declination: -1.0 *  self.declination.clone() }
+        Bearing3D {
+            heading: Position3D::new(self.heading.x, self.left.x, self.up.x),
+            left: Position3D::new(self.heading.y, self.left.y, self.up.y),
+            up: Position3D::new(self.heading.z, self.left.z, self.up.z),
+        }
     }
 }
 
+/// A constant pull on a turtle's heading, such as gravity or light.  After
+/// every move the heading turns towards `direction` by `susceptibility`
+/// times |H × T|, as in The Algorithmic Beauty of Plants, so it bends most
+/// when square to the pull and not at all when in line with it.
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct Tropism<P> {
+    pub direction: P,
+    pub susceptibility: f64,
+}
+
 /// A set of turtle drawer functions for an L-system, which maps a string to a list of lines
 pub trait Turtle {
     type Line;
     type Bearing;
     type Position;
 
-    /// move forward dist units
+    /// move forward dist units, then bend towards any tropism
     fn move_forward(&mut self, dist: f64);
 
     /// turn angle radians
@@ -227,24 +621,37 @@ pub struct Turtle2D {
     orientations: Vec<(Position2D, Bearing2D)>,
     current_position: Position2D,
     current_bearing: Bearing2D,
+    tropism: Option<Tropism<Position2D>>,
+}
+
+impl Default for Turtle2D {
+    fn default() -> Self {
+        Turtle2D::new()
+    }
 }
 
 impl Turtle2D {
-    pub(crate) fn new() -> Turtle2D {
+    pub fn new() -> Turtle2D {
         Turtle2D {
             orientations: vec![],
             current_position: Position2D { x: num_traits::identities::zero(), y: num_traits::identities::zero() },
             current_bearing: Bearing2D { rotation: num_traits::identities::zero()},
+            tropism: None,
         }
     }
 
-    pub(crate) fn new_from(x: f64, y: f64, rotation: f64) -> Turtle2D {
+    pub fn new_from(x: f64, y: f64, rotation: f64) -> Turtle2D {
         Turtle2D {
             orientations: vec![],
-            current_position: Position2D { x:x, y: y },
-            current_bearing: Bearing2D { rotation: rotation },
+            current_position: Position2D { x, y },
+            current_bearing: Bearing2D { rotation },
+            tropism: None,
         }
     }
+
+    pub fn set_tropism(&mut self, tropism: Option<Tropism<Position2D>>) {
+        self.tropism = tropism;
+    }
 }
 
 impl Turtle for Turtle2D {
@@ -253,62 +660,88 @@ impl Turtle for Turtle2D {
     type Position = Position2D;
 
     fn move_forward(&mut self, dist: f64) {
+        let (sin, cos) = self.current_bearing.rotation.sin_cos();
         self.current_position = Self::Position {
-            x: self.current_position.x.clone() + dist * self.current_bearing.rotation.cos(),
-            y: self.current_position.y.clone() + dist * self.current_bearing.rotation.sin(),
+            x: self.current_position.x + dist * cos,
+            y: self.current_position.y + dist * sin,
+        };
+        if let Some(tropism) = self.tropism {
+            // in the plane, H × T is a rotation by its signed length
+            let bend = cos * tropism.direction.y - sin * tropism.direction.x;
+            self.current_bearing.rotation += tropism.susceptibility * bend;
         }
     }
 
     fn turn(&mut self, bearing: &Self::Bearing) {
-        self.current_bearing = Self::Bearing { rotation: self.current_bearing.rotation.clone() + bearing.rotation.clone() }
+        self.current_bearing = Self::Bearing { rotation: self.current_bearing.rotation + bearing.rotation }
     }
 
     fn push(&mut self) {
-        self.orientations.push((self.current_position.clone(), self.current_bearing.clone()))
+        self.orientations.push((self.current_position, self.current_bearing))
     }
 
     fn pop(&mut self) {
-        match self.orientations.pop() {
-            Some((p, b)) => {
-                self.current_position = p;
-                self.current_bearing = b;
-            }
-            None => {}
+        if let Some((p, b)) = self.orientations.pop() {
+            self.current_position = p;
+            self.current_bearing = b;
         }
     }
 
     fn position(&self) -> Self::Position {
-        return self.current_position;
+        self.current_position
     }
 
     fn bearing(&self) -> Self::Bearing {
-        return self.current_bearing;
+        self.current_bearing
     }
 }
 
 
+/// A turtle carrying a full HLU rotation frame, so turns, pitches and rolls
+/// compose correctly.
 pub struct Turtle3D {
     orientations: Vec<(Position3D, Bearing3D)>,
     current_position: Position3D,
     current_bearing: Bearing3D,
+    tropism: Option<Tropism<Position3D>>,
+}
+
+impl Default for Turtle3D {
+    fn default() -> Self {
+        Turtle3D::new()
+    }
 }
 
 impl Turtle3D {
-    pub(crate) fn new() -> Turtle3D {
-        Turtle3D {
-            orientations: vec![],
-            current_position: Position3D { x: num_traits::identities::zero(), y: num_traits::identities::zero(), z: num_traits::identities::zero() },
-            current_bearing: Bearing3D { azimuth: num_traits::identities::zero(), declination: num_traits::identities::zero() },
-        }
+    /// a turtle at the origin heading up the y axis with its up vector along z
+    pub fn new() -> Turtle3D {
+        Turtle3D::new_from(
+            Position3D::new(0.0, 0.0, 0.0),
+            Position3D::new(0.0, 1.0, 0.0),
+            Position3D::new(0.0, 0.0, 1.0),
+        )
     }
 
-    pub(crate) fn new_from(x: f64, y: f64, z: f64, azimuth: f64, declination: f64) -> Turtle3D {
+    /// a turtle at `position` facing `heading`, rolled so its up vector is as
+    /// close to `up` as possible
+    pub fn new_from(position: Position3D, heading: Position3D, up: Position3D) -> Turtle3D {
+        let heading = heading.normalized();
+        let left = up.cross(&heading).normalized();
         Turtle3D {
             orientations: vec![],
-            current_position: Position3D { x: x, y: y, z: z},
-            current_bearing: Bearing3D { azimuth: azimuth, declination: declination },
+            current_position: position,
+            current_bearing: Bearing3D {
+                heading,
+                left,
+                up: heading.cross(&left),
+            },
+            tropism: None,
         }
     }
+
+    pub fn set_tropism(&mut self, tropism: Option<Tropism<Position3D>>) {
+        self.tropism = tropism;
+    }
 }
 
 impl Turtle for Turtle3D {
@@ -317,40 +750,41 @@ impl Turtle for Turtle3D {
     type Position = Position3D;
 
     fn move_forward(&mut self, dist: f64) {
-        self.current_position = Self::Position {
-            x: self.current_position.x.clone() + dist * self.current_bearing.azimuth.cos() * self.current_bearing.declination.sin(),
-            y: self.current_position.y.clone() + dist * self.current_bearing.azimuth.sin() * self.current_bearing.declination.sin(),
-            z: self.current_position.z.clone() + dist * self.current_bearing.declination.cos(),
+        self.current_position = self.current_position.add(&self.current_bearing.heading.scale(dist));
+        if let Some(tropism) = self.tropism {
+            let axis = self.current_bearing.heading.cross(&tropism.direction);
+            let angle = tropism.susceptibility * axis.length();
+            if angle != 0.0 {
+                let axis = axis.normalized();
+                let b = &mut self.current_bearing;
+                b.heading = b.heading.rotated(&axis, angle);
+                b.left = b.left.rotated(&axis, angle);
+                b.up = b.up.rotated(&axis, angle);
+            }
         }
     }
 
     fn turn(&mut self, bearing: &Self::Bearing) {
-        self.current_bearing = Self::Bearing {
-            azimuth: self.current_bearing.azimuth.clone() + bearing.azimuth,
-            declination: self.current_bearing.declination.clone() + bearing.declination,
-        }
+        self.current_bearing = self.current_bearing.then(bearing)
     }
 
     fn push(&mut self) {
-        self.orientations.push((self.current_position.clone(), self.current_bearing.clone()))
+        self.orientations.push((self.current_position, self.current_bearing))
     }
 
     fn pop(&mut self) {
-        match self.orientations.pop() {
-            Some((p, b)) => {
-                self.current_position = p;
-                self.current_bearing = b;
-            }
-            None => {}
+        if let Some((p, b)) = self.orientations.pop() {
+            self.current_position = p;
+            self.current_bearing = b;
         }
     }
 
     fn position(&self) -> Self::Position {
-        return self.current_position;
+        self.current_position
     }
 
     fn bearing(&self) -> Self::Bearing {
-        return self.current_bearing;
+        self.current_bearing
     }
 }
 
@@ -358,105 +792,868 @@ impl Turtle for Turtle3D {
 pub trait LineDrawer<
     T,
     TT> where TT: Turtle {
-    /// perform a mapping of atoms to lines and markers
-    fn map(&self, input: &Vec<T>, turtle: &mut TT) -> (Vec<TT::Line>, Vec<TT::Position>);
+    /// perform a mapping of atoms to lines and markers, drawing any
+    /// randomness from `rng`
+    fn map(&self, input: &[T], turtle: &mut TT, rng: &mut dyn RngCore) -> (Vec<TT::Line>, Vec<MarkerOf<TT>>);
 
-    fn get_move_distance(&self) -> f64;
+    fn get_move_distance(&self, rng: &mut dyn RngCore) -> f64;
 
     fn get_move_bearing(&self) -> TT::Bearing;
 }
 
 
+/// Where a line sits in the branching structure of a drawing.
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct LineInfo {
+    /// the number of branches (`[`) enclosing the line
+    pub depth: usize,
+    /// the path length from the root to the start of the line
+    pub distance: f64,
+    /// the width of the line relative to the plant's base width, set by `!`
+    /// or by `pipe_model`
+    pub width: f64,
+    /// the line this one grows from, if any
+    pub parent: Option<usize>,
+    /// the entry of the palette to draw the line with, set by `'`
+    pub color: usize,
+    /// the generation of the derivation the line's symbol appeared in, or
+    /// 0 when the drawer wasn't told
+    pub generation: usize,
+}
+
+/// A filled surface such as a leaf or petal, traced between `{` and `}`
+/// with a vertex at each `.`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polygon<P> {
+    pub points: Vec<P>,
+    /// the entry of the palette to fill the polygon with
+    pub color: usize,
+    /// the last line drawn before the polygon was started, if any
+    pub parent: Option<usize>,
+}
+
+/// What a marker stands for, which decides how it is painted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MarkerKind {
+    Flower,
+    Leaf,
+    Bud,
+    Fruit,
+    /// a kind known only to whoever paints it
+    Custom(u32),
+}
+
+impl MarkerKind {
+    /// a kind by name, or a custom kind by number
+    pub fn parse(name: &str) -> Option<MarkerKind> {
+        match name {
+            "flower" => Some(MarkerKind::Flower),
+            "leaf" => Some(MarkerKind::Leaf),
+            "bud" => Some(MarkerKind::Bud),
+            "fruit" => Some(MarkerKind::Fruit),
+            _ => name.parse().ok().map(MarkerKind::Custom),
+        }
+    }
+}
+
+impl fmt::Display for MarkerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MarkerKind::Flower => write!(f, "flower"),
+            MarkerKind::Leaf => write!(f, "leaf"),
+            MarkerKind::Bud => write!(f, "bud"),
+            MarkerKind::Fruit => write!(f, "fruit"),
+            MarkerKind::Custom(id) => write!(f, "{}", id),
+        }
+    }
+}
+
+/// the symbols drawers place markers at unless told otherwise: `M` for a
+/// flower
+pub fn default_marker_symbols() -> HashMap<char, MarkerKind> {
+    [('M', MarkerKind::Flower)].iter().copied().collect()
+}
+
+/// What a drawer does when it meets a symbol.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// move forward, drawing a line
+    Forward,
+    /// move forward without drawing
+    Move,
+    /// turn left or right by the drawer's angle; in 2D, left is `+`
+    TurnLeft,
+    TurnRight,
+    /// pitch down or up around the left vector, in 3D only
+    PitchDown,
+    PitchUp,
+    /// roll left or right around the heading, in 3D only
+    RollLeft,
+    RollRight,
+    TurnAround,
+    /// start and end a branch
+    Push,
+    Pop,
+    /// narrow the lines that follow, as `!`
+    Narrow,
+    /// move the lines that follow to the next colour, as `'`
+    Recolor,
+    BeginPolygon,
+    /// add a vertex to the open polygon, as `.`
+    Vertex,
+    EndPolygon,
+    Marker(MarkerKind),
+    /// do nothing, for symbols that only steer the rewriting
+    Ignore,
+}
+
+impl Action {
+    /// an action by name, or a marker by its kind
+    pub fn parse(name: &str) -> Option<Action> {
+        match name {
+            "forward" => Some(Action::Forward),
+            "move" => Some(Action::Move),
+            "left" => Some(Action::TurnLeft),
+            "right" => Some(Action::TurnRight),
+            "pitch-down" => Some(Action::PitchDown),
+            "pitch-up" => Some(Action::PitchUp),
+            "roll-left" => Some(Action::RollLeft),
+            "roll-right" => Some(Action::RollRight),
+            "turn-around" => Some(Action::TurnAround),
+            "push" => Some(Action::Push),
+            "pop" => Some(Action::Pop),
+            "narrow" => Some(Action::Narrow),
+            "recolor" => Some(Action::Recolor),
+            "begin-polygon" => Some(Action::BeginPolygon),
+            "vertex" => Some(Action::Vertex),
+            "end-polygon" => Some(Action::EndPolygon),
+            "ignore" => Some(Action::Ignore),
+            _ => MarkerKind::parse(name).map(Action::Marker),
+        }
+    }
+}
+
+/// The action bound to each symbol a drawer understands.  Symbols without
+/// an action are skipped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SymbolTable {
+    actions: HashMap<char, Action>,
+}
+
+impl SymbolTable {
+    pub fn empty() -> SymbolTable {
+        SymbolTable { actions: HashMap::new() }
+    }
+
+    /// the symbols `PlantDrawer2D` has always understood: `F`, `f`, `G`,
+    /// `+`, `-`, `!`, `'`, `[`, `]`, `{`, `.`, `}`, and the marker symbols
+    /// of `default_marker_symbols`
+    pub fn new_2d() -> SymbolTable {
+        let mut table = SymbolTable::empty();
+        for &(symbol, action) in [
+            ('F', Action::Forward),
+            ('f', Action::Move),
+            ('G', Action::Move),
+            ('+', Action::TurnLeft),
+            ('-', Action::TurnRight),
+            ('!', Action::Narrow),
+            ('\'', Action::Recolor),
+            ('[', Action::Push),
+            (']', Action::Pop),
+            ('{', Action::BeginPolygon),
+            ('.', Action::Vertex),
+            ('}', Action::EndPolygon),
+        ].iter() {
+            table.bind(symbol, action);
+        }
+        for (symbol, kind) in default_marker_symbols() {
+            table.bind(symbol, Action::Marker(kind));
+        }
+        table
+    }
+
+    /// the 2D symbols with the rotations of `PlantDrawer3D`: `&`, `^`,
+    /// `\`, `/` and `|`
+    pub fn new_3d() -> SymbolTable {
+        let mut table = SymbolTable::new_2d();
+        table.bind('&', Action::PitchDown);
+        table.bind('^', Action::PitchUp);
+        table.bind('\\', Action::RollLeft);
+        table.bind('/', Action::RollRight);
+        table.bind('|', Action::TurnAround);
+        table
+    }
+
+    /// bind `symbol` to `action`, replacing any action before
+    pub fn bind(&mut self, symbol: char, action: Action) {
+        self.actions.insert(symbol, action);
+    }
+
+    pub fn action(&self, symbol: char) -> Option<Action> {
+        self.actions.get(&symbol).copied()
+    }
+
+    /// Bind symbols written as `G forward, | turn-around, A ignore`, each a
+    /// symbol and the name of an action or a marker kind.  The error names
+    /// the entry that could not be read, and entries before it stay bound.
+    pub fn bind_all(&mut self, text: &str) -> Result<(), String> {
+        self.bind_entries(text, "an action", Action::parse)
+    }
+
+    /// like `bind_all`, but only for marker kinds, as in `B bud, Q 7`
+    pub fn bind_markers(&mut self, text: &str) -> Result<(), String> {
+        self.bind_entries(text, "a marker kind", |name| MarkerKind::parse(name).map(Action::Marker))
+    }
+
+    fn bind_entries(&mut self, text: &str, expected: &str, parse: impl Fn(&str) -> Option<Action>) -> Result<(), String> {
+        for entry in text.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut words = entry.split_whitespace();
+            let symbol = words.next().and_then(|w| {
+                let mut chars = w.chars();
+                chars.next().filter(|_| chars.next().is_none())
+            });
+            match (symbol, words.next().and_then(&parse), words.next()) {
+                (Some(symbol), Some(action), None) => self.bind(symbol, action),
+                _ => return Err(format!("expected a symbol and {}, found `{}`", expected, entry)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Something drawn at a point of the plant rather than traced, such as a
+/// flower or a fruit, facing the way the turtle was heading.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Marker<P, B> {
+    pub kind: MarkerKind,
+    pub position: P,
+    pub bearing: B,
+    /// size relative to the kind's usual size, set by a parameter such as
+    /// `M(2)`
+    pub scale: f64,
+    /// the palette entry picked by `'`, counted the same way as for lines,
+    /// or `None` if no `'` came before the marker and it keeps the kind's
+    /// own colour
+    pub color: Option<usize>,
+}
+
+pub type Marker2D = Marker<Position2D, Bearing2D>;
+pub type Marker3D = Marker<Position3D, Bearing3D>;
+/// the markers placed by a turtle
+pub type MarkerOf<TT> = Marker<<TT as Turtle>::Position, <TT as Turtle>::Bearing>;
+
+impl<P, B> Marker<P, B> {
+    /// a marker of the usual size and colour
+    pub fn new(kind: MarkerKind, position: P, bearing: B) -> Marker<P, B> {
+        Marker { kind, position, bearing, scale: 1.0, color: None }
+    }
+}
+
+/// Everything a drawer traces from a string of atoms: lines with a
+/// description of each, markers, and polygons.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Drawing<L, P, B> {
+    pub lines: Vec<L>,
+    /// where each of `lines` sits in the plant
+    pub info: Vec<LineInfo>,
+    pub markers: Vec<Marker<P, B>>,
+    pub polygons: Vec<Polygon<P>>,
+}
+
+pub type Drawing2D = Drawing<Line2D, Position2D, Bearing2D>;
+pub type Drawing3D = Drawing<Line3D, Position3D, Bearing3D>;
+
+impl<L, P, B> Default for Drawing<L, P, B> {
+    fn default() -> Self {
+        Drawing { lines: vec![], info: vec![], markers: vec![], polygons: vec![] }
+    }
+}
+
+/// The state a drawer keeps alongside its turtle to describe each line:
+/// the current width and colour, the path length so far and the last line
+/// drawn, saved and restored with the turtle at each branch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Branching {
+    depth: usize,
+    distance: f64,
+    width: f64,
+    /// the colour index picked by the last `'`, if there was one
+    color: Option<usize>,
+    parent: Option<usize>,
+}
+
+impl Branching {
+    fn new() -> Branching {
+        Branching { depth: 0, distance: 0.0, width: 1.0, color: None, parent: None }
+    }
+
+    /// describe line `index`, `length` long and drawn by a symbol born in
+    /// `generation`, and move to its end
+    fn line(&mut self, index: usize, length: f64, generation: usize) -> LineInfo {
+        let info = LineInfo {
+            depth: self.depth,
+            distance: self.distance,
+            width: self.width,
+            parent: self.parent,
+            color: self.color.unwrap_or(0),
+            generation,
+        };
+        self.distance += length.abs();
+        self.parent = Some(index);
+        info
+    }
+
+    /// move `length` without drawing a line
+    fn skip(&mut self, length: f64) {
+        self.distance += length.abs();
+    }
+
+    /// `!`, narrowing by `decrement` or setting the width to its parameter
+    fn narrow<T: Symbol>(&mut self, s: &T, decrement: f64) {
+        self.width = s.param(0).unwrap_or((self.width - decrement).max(0.0));
+    }
+
+    /// `'`, moving to the next colour or the one given by its parameter
+    fn recolor<T: Symbol>(&mut self, s: &T) {
+        let next = self.color.map_or(1, |c| c + 1);
+        self.color = Some(s.param(0).map_or(next, |c| c.max(0.0) as usize));
+    }
+}
+
+/// Give every line the width of a pipe model: the cross-sections of the
+/// branches growing from a line add up to its own, so a line carrying `n`
+/// tips is `n^(1 / exponent)` times as wide as a tip.  An exponent of 2 is
+/// da Vinci's rule; larger exponents taper less.  Widths are scaled so the
+/// line carrying the most tips, normally the trunk, has the width of 1 that
+/// `!` starts from, and the tips taper down from it.
+pub fn pipe_model(info: &mut [LineInfo], exponent: f64) {
+    let mut tips = vec![0.0_f64; info.len()];
+    // children always come after their parents, so walking backwards sees
+    // every line's children before the line itself
+    for i in (0..info.len()).rev() {
+        if tips[i] == 0.0 {
+            tips[i] = 1.0;
+        }
+        if let Some(parent) = info[i].parent {
+            tips[parent] += tips[i];
+        }
+    }
+    let most = tips.iter().copied().fold(1.0, f64::max);
+    for (line, tips) in info.iter_mut().zip(tips) {
+        line.width = (tips / most).powf(1.0 / exponent);
+    }
+}
+
+
 pub struct PlantDrawer2D<TT>
     where TT: Turtle
 {
     pub move_distance: f64,
     pub move_bearing: TT::Bearing,
+    /// how much each `!` narrows the lines after it, relative to the
+    /// starting width of 1
+    pub width_decrement: f64,
+    /// what each symbol does
+    pub symbols: SymbolTable,
 }
 
 
-pub struct PlantDrawer3D<TT>
-    where TT: Turtle
-{
+/// Draws with a 3D turtle.  Every rotation symbol turns by the same angle,
+/// in radians.
+pub struct PlantDrawer3D {
     pub move_distance: f64,
-    pub move_bearing: TT::Bearing,
+    pub angle: f64,
+    /// how much each `!` narrows the lines after it
+    pub width_decrement: f64,
+    /// what each symbol does
+    pub symbols: SymbolTable,
 }
 
 
-impl<TT> LineDrawer<char, TT> for PlantDrawer2D<TT>
+impl<TT> PlantDrawer2D<TT>
     where TT: Turtle<Line=Line2D, Bearing=Bearing2D, Position=Position2D> {
-    fn map(&self, input: &Vec<char>, turtle: &mut TT) -> (Vec<TT::Line>, Vec<TT::Position>)
-    {
-        let mut lines = Vec::<TT::Line>::new();
-        let mut markers = Vec::<TT::Position>::new();
-        for s in input.iter() {
-            match s {
-                'F' => {
+    /// like `map`, but also describe where each line sits in the plant and
+    /// trace polygons
+    pub fn draw<T: Symbol>(&self, input: &[T], turtle: &mut TT, rng: &mut dyn RngCore) -> Drawing2D {
+        self.draw_born(input, &[], turtle, rng)
+    }
+
+    /// like `draw`, also giving each line the generation its symbol was
+    /// born in, as given by `LSystem::born`
+    pub fn draw_born<T: Symbol>(&self, input: &[T], born: &[usize], turtle: &mut TT, rng: &mut dyn RngCore) -> Drawing2D {
+        let mut drawing = Drawing2D::default();
+        let mut branches = Vec::<Branching>::new();
+        let mut state = Branching::new();
+        // polygons started but not yet closed, innermost last
+        let mut open = Vec::<Polygon<Position2D>>::new();
+        for (i, s) in input.iter().enumerate() {
+            match self.symbols.action(s.symbol()) {
+                Some(Action::Forward) => {
                     let start = turtle.position();
-                    turtle.move_forward(self.get_move_distance());
-                    let line = TT::Line {
-                        start: start,
+                    let step = s.param(0).unwrap_or_else(|| LineDrawer::<T, TT>::get_move_distance(self, rng));
+                    turtle.move_forward(step);
+                    drawing.lines.push(Line2D {
+                        start,
                         end: turtle.position(),
-                    };
-                    lines.push(line);
+                    });
+                    drawing.info.push(state.line(drawing.lines.len() - 1, step, born.get(i).copied().unwrap_or(0)));
+                }
+                Some(Action::Move) => {
+                    let step = s.param(0).unwrap_or_else(|| LineDrawer::<T, TT>::get_move_distance(self, rng));
+                    turtle.move_forward(step);
+                    state.skip(step);
+                }
+                Some(Action::TurnRight) => match s.param(0) {
+                    Some(a) => turtle.turn(&Bearing2D { rotation: -a.to_radians() }),
+                    None => turtle.turn(&LineDrawer::<T, TT>::get_move_bearing(self).flip()),
+                },
+                Some(Action::TurnLeft) => match s.param(0) {
+                    Some(a) => turtle.turn(&Bearing2D { rotation: a.to_radians() }),
+                    None => turtle.turn(&LineDrawer::<T, TT>::get_move_bearing(self)),
+                },
+                Some(Action::TurnAround) => turtle.turn(&Bearing2D { rotation: std::f64::consts::PI }),
+                Some(Action::Narrow) => state.narrow(s, self.width_decrement),
+                Some(Action::Recolor) => state.recolor(s),
+                Some(Action::Push) => {
+                    turtle.push();
+                    branches.push(state);
+                    state.depth += 1;
+                }
+                Some(Action::Pop) => {
+                    turtle.pop();
+                    if let Some(branch) = branches.pop() {
+                        state = branch;
+                    }
                 }
-                '-' => turtle.turn(&self.get_move_bearing().flip()),
-                '+' => turtle.turn(&self.get_move_bearing()),
-                '[' => turtle.push(),
-                ']' => turtle.pop(),
-                'M' => {
-                    markers.push(turtle.position().clone());
+                Some(Action::BeginPolygon) => open.push(Polygon { points: vec![], color: state.color.unwrap_or(0), parent: state.parent }),
+                Some(Action::Vertex) => if let Some(polygon) = open.last_mut() {
+                    polygon.points.push(turtle.position());
                 },
-                _ => {}
+                Some(Action::EndPolygon) => match open.pop() {
+                    Some(polygon) if polygon.points.len() >= 3 => drawing.polygons.push(polygon),
+                    _ => {}
+                },
+                Some(Action::Marker(kind)) => drawing.markers.push(Marker {
+                    kind,
+                    position: turtle.position(),
+                    bearing: turtle.bearing(),
+                    scale: s.param(0).unwrap_or(1.0),
+                    color: state.color,
+                }),
+                // a flat turtle can't pitch or roll
+                Some(Action::PitchDown | Action::PitchUp | Action::RollLeft | Action::RollRight | Action::Ignore) | None => {}
             }
         }
-        return (lines, markers);
+        drawing
+    }
+}
+
+/// Atoms are interpreted by the action their symbol is bound to in the
+/// drawer's table, described here for `SymbolTable::new_2d`.  A parametric
+/// `F(x)` moves x units instead of the drawer's move distance, and `+(a)` /
+/// `-(a)` turn by a degrees instead of the drawer's bearing.  `!` narrows
+/// the lines that follow by the drawer's width decrement, and `!(w)` sets
+/// their width to w.  `'` moves the lines that follow to the next colour of
+/// the palette, and `'(i)` to colour i.  `f` and `G` move without drawing,
+/// and `{` starts a polygon with a vertex at each `.` until `}`, filled in
+/// the current colour.  Each marker symbol places a marker of its kind,
+/// scaled by its parameter.
+impl<T, TT> LineDrawer<T, TT> for PlantDrawer2D<TT>
+    where T: Symbol, TT: Turtle<Line=Line2D, Bearing=Bearing2D, Position=Position2D> {
+    fn map(&self, input: &[T], turtle: &mut TT, rng: &mut dyn RngCore) -> (Vec<TT::Line>, Vec<MarkerOf<TT>>)
+    {
+        let drawing = self.draw(input, turtle, rng);
+        (drawing.lines, drawing.markers)
     }
 
-    fn get_move_distance(&self) -> f64 {
-        return self.move_distance;
+    fn get_move_distance(&self, _rng: &mut dyn RngCore) -> f64 {
+        self.move_distance
     }
 
     fn get_move_bearing(&self) -> TT::Bearing {
-        return self.move_bearing;
+        self.move_bearing
     }
 }
 
-impl<TT> LineDrawer<char, TT> for PlantDrawer3D<TT>
-    where TT: Turtle<Line=Line3D, Bearing=Bearing3D, Position=Position3D> {
-    fn map(&self, input: &Vec<char>, turtle: &mut TT) -> (Vec<TT::Line>, Vec<TT::Position>) {
-        let mut lines = Vec::<TT::Line>::new();
-        let mut markers = Vec::<TT::Position>::new();
-        for s in input.iter() {
-            match s {
-                'F' => {
+/// Atoms are interpreted by the action their symbol is bound to, with
+/// `SymbolTable::new_3d` following The Algorithmic Beauty of Plants:
+///
+/// * `+` / `-` turn left / right around the up vector
+/// * `&` / `^` pitch down / up around the left vector
+/// * `\` / `/` roll left / right around the heading
+/// * `|` turns around
+///
+/// A parametric `F(x)` moves x units instead of the drawer's move distance,
+/// and a rotation with a parameter turns by that many degrees instead of the
+/// drawer's angle.  `!`, `'`, `f`, `G` and polygons work as in 2D.
+impl<T, TT> LineDrawer<T, TT> for PlantDrawer3D
+    where T: Symbol, TT: Turtle<Line=Line3D, Bearing=Bearing3D, Position=Position3D> {
+    fn map(&self, input: &[T], turtle: &mut TT, rng: &mut dyn RngCore) -> (Vec<TT::Line>, Vec<MarkerOf<TT>>) {
+        let drawing = self.draw(input, turtle, rng);
+        (drawing.lines, drawing.markers)
+    }
+
+    fn get_move_distance(&self, rng: &mut dyn RngCore) -> f64 {
+        self.move_distance + rng.gen::<f64>()
+    }
+
+    fn get_move_bearing(&self) -> TT::Bearing {
+        Bearing3D::yaw(self.angle)
+    }
+}
+
+impl PlantDrawer3D {
+    /// like `map`, but also describe where each line sits in the plant and
+    /// trace polygons
+    pub fn draw<T, TT>(&self, input: &[T], turtle: &mut TT, rng: &mut dyn RngCore) -> Drawing3D
+        where T: Symbol, TT: Turtle<Line=Line3D, Bearing=Bearing3D, Position=Position3D>
+    {
+        self.draw_born(input, &[], turtle, rng)
+    }
+
+    /// like `draw`, also giving each line the generation its symbol was
+    /// born in, as given by `LSystem::born`
+    pub fn draw_born<T, TT>(&self, input: &[T], born: &[usize], turtle: &mut TT, rng: &mut dyn RngCore) -> Drawing3D
+        where T: Symbol, TT: Turtle<Line=Line3D, Bearing=Bearing3D, Position=Position3D>
+    {
+        let mut drawing = Drawing3D::default();
+        let mut branches = Vec::<Branching>::new();
+        let mut state = Branching::new();
+        let mut open = Vec::<Polygon<Position3D>>::new();
+        for (i, s) in input.iter().enumerate() {
+            let angle = s.param(0).map_or(self.angle, f64::to_radians);
+            match self.symbols.action(s.symbol()) {
+                Some(Action::Forward) => {
                     let start = turtle.position();
-                    turtle.move_forward(self.get_move_distance());
-                    let line = TT::Line {
-                        start: start,
+                    let distance = s.param(0).unwrap_or_else(|| LineDrawer::<T, TT>::get_move_distance(self, rng));
+                    turtle.move_forward(distance);
+                    drawing.lines.push(Line3D {
+                        start,
                         end: turtle.position(),
-                    };
-                    lines.push(line);
+                    });
+                    drawing.info.push(state.line(drawing.lines.len() - 1, distance, born.get(i).copied().unwrap_or(0)));
+                }
+                Some(Action::Move) => {
+                    let distance = s.param(0).unwrap_or_else(|| LineDrawer::<T, TT>::get_move_distance(self, rng));
+                    turtle.move_forward(distance);
+                    state.skip(distance);
                 }
-                '-' => turtle.turn(&self.get_move_bearing().flip()),
-                '+' => turtle.turn(&self.get_move_bearing()),
-                '[' => turtle.push(),
-                ']' => turtle.pop(),
-                'M' => {
-                    markers.push(turtle.position().clone());
+                Some(Action::TurnLeft) => turtle.turn(&Bearing3D::yaw(angle)),
+                Some(Action::TurnRight) => turtle.turn(&Bearing3D::yaw(-angle)),
+                Some(Action::PitchDown) => turtle.turn(&Bearing3D::pitch(angle)),
+                Some(Action::PitchUp) => turtle.turn(&Bearing3D::pitch(-angle)),
+                Some(Action::RollLeft) => turtle.turn(&Bearing3D::roll(angle)),
+                Some(Action::RollRight) => turtle.turn(&Bearing3D::roll(-angle)),
+                Some(Action::TurnAround) => turtle.turn(&Bearing3D::yaw(std::f64::consts::PI)),
+                Some(Action::Narrow) => state.narrow(s, self.width_decrement),
+                Some(Action::Recolor) => state.recolor(s),
+                Some(Action::Push) => {
+                    turtle.push();
+                    branches.push(state);
+                    state.depth += 1;
+                }
+                Some(Action::Pop) => {
+                    turtle.pop();
+                    if let Some(branch) = branches.pop() {
+                        state = branch;
+                    }
+                }
+                Some(Action::BeginPolygon) => open.push(Polygon { points: vec![], color: state.color.unwrap_or(0), parent: state.parent }),
+                Some(Action::Vertex) => if let Some(polygon) = open.last_mut() {
+                    polygon.points.push(turtle.position());
                 },
-                _ => {}
+                Some(Action::EndPolygon) => match open.pop() {
+                    Some(polygon) if polygon.points.len() >= 3 => drawing.polygons.push(polygon),
+                    _ => {}
+                },
+                Some(Action::Marker(kind)) => drawing.markers.push(Marker {
+                    kind,
+                    position: turtle.position(),
+                    bearing: turtle.bearing(),
+                    scale: s.param(0).unwrap_or(1.0),
+                    color: state.color,
+                }),
+                Some(Action::Ignore) | None => {}
             }
         }
-        return (lines, markers);
+        drawing
     }
+}
 
-    fn get_move_distance(&self) -> f64 {
-        return self.move_distance + random::<f64>();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stochastic_rules() -> MapRules<char> {
+        let mut rules = MapRules::new();
+        rules.set_str_prob('X', "F[+X]-X", 0.5);
+        rules.set_str_prob('X', "FX", 0.5);
+        rules.set_str('F', "FF");
+        rules
     }
 
-    fn get_move_bearing(&self) -> TT::Bearing {
-        return self.move_bearing;
+    /// a 2D drawer taking unit steps and turning by half a radian
+    fn drawer(symbols: SymbolTable) -> PlantDrawer2D<Turtle2D> {
+        PlantDrawer2D {
+            move_distance: 1.0,
+            move_bearing: Bearing2D { rotation: 0.5 },
+            width_decrement: 0.25,
+            symbols,
+        }
+    }
+
+    fn frequencies(selection: Selection, weights: &[f64]) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut counts = vec![0.0; weights.len() + 1];
+        let n = 20000;
+        for _ in 0..n {
+            match selection.choose(weights, &mut rng) {
+                Some(i) => counts[i] += 1.0,
+                None => counts[weights.len()] += 1.0,
+            }
+        }
+        counts.iter().map(|c| c / n as f64).collect()
+    }
+
+    #[test]
+    fn weighted_selection_follows_weights() {
+        let f = frequencies(Selection::Weighted, &[0.2, 0.6, 0.2]);
+        assert!((f[0] - 0.2).abs() < 0.02);
+        assert!((f[1] - 0.6).abs() < 0.02);
+        assert_eq!(f[3], 0.0);
+
+        // the legacy policy lets earlier productions shadow later ones
+        let f = frequencies(Selection::FirstMatch, &[0.5, 0.5]);
+        assert!((f[1] - 0.25).abs() < 0.02);
+        assert!((f[2] - 0.25).abs() < 0.02);
+    }
+
+    #[test]
+    fn strict_rules_must_sum_to_one() {
+        let mut rules = MapRules::with_selection(Selection::Strict);
+        rules.set_str_prob('X', "FX", 0.5);
+        rules.set_str_prob('X', "X", 0.25);
+        assert_eq!(rules.validate(), Err(WeightError { atom: 'X', total: 0.75 }));
+        rules.set_str_prob('X', "M", 0.25);
+        assert_eq!(rules.validate(), Ok(()));
+    }
+
+    #[test]
+    fn counts_generations() {
+        let mut rules = MapRules::new();
+        rules.set_str('A', "AB");
+        let mut system = LSystem::new_seeded(rules, vec!['A'], 0);
+        assert_eq!(system.nth_generation(0), &['A']);
+        assert_eq!(to_string(system.nth_generation(3)), "ABBB");
+        assert_eq!(system.generation(), 3);
+        assert_eq!(to_string(system.nth_generation(1)), "AB");
+        assert_eq!(system.generation(), 1);
+    }
+
+    #[test]
+    fn identity_rewrites_are_a_fixed_point() {
+        let mut rules = MapRules::new();
+        rules.set_str('A', "B");
+        rules.set_str('B', "B");
+        let mut system = LSystem::new_seeded(rules, vec!['A', 'A'], 0);
+        assert_eq!(to_string(&system.next().unwrap()), "BB");
+        assert!(system.next().is_none());
+        system.reset();
+        assert_eq!(system.step_until_stable(10), Some(1));
+        assert_eq!(to_string(system.state()), "BB");
+
+        // a system that grows forever never settles
+        let mut rules = MapRules::new();
+        rules.set_str('A', "AB");
+        let mut system = LSystem::new_seeded(rules, vec!['A'], 0);
+        assert_eq!(system.step_until_stable(5), None);
+        assert_eq!(system.generation(), 5);
+    }
+
+    #[test]
+    fn records_when_atoms_were_born() {
+        let mut rules = MapRules::new();
+        rules.set_str('A', "F[A]A");
+        rules.set_str('F', "FF");
+        let mut system = LSystem::new_seeded(rules, vec!['A'], 0);
+        system.nth_generation(2);
+        assert_eq!(to_string(system.state()), "FF[F[A]A]F[A]A");
+        // the apices and the segments they keep dividing stay as old as they
+        // were when they first appeared
+        assert_eq!(system.born(), &[1, 1, 1, 2, 2, 0, 2, 0, 1, 2, 2, 0, 2, 0]);
+
+        let drawer = drawer(SymbolTable::new_2d());
+        let plant = drawer.draw_born(system.state(), system.born(), &mut Turtle2D::new(), &mut StdRng::seed_from_u64(0));
+        let generations: Vec<usize> = plant.info.iter().map(|i| i.generation).collect();
+        assert_eq!(generations, vec![1, 1, 2, 2]);
+        system.reset();
+        assert_eq!(system.born(), &[0]);
+    }
+
+    fn assert_close(a: Position3D, b: Position3D) {
+        assert!(a.sub(&b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn turtle_frame_composes() {
+        let quarter = std::f64::consts::FRAC_PI_2;
+        let mut turtle = Turtle3D::new();
+        // pitching a quarter turn points the heading along the old up vector
+        turtle.turn(&Bearing3D::pitch(quarter));
+        assert_close(turtle.bearing().heading, Position3D::new(0.0, 0.0, 1.0));
+        // rolling keeps the heading but spins left and up around it
+        turtle.turn(&Bearing3D::roll(quarter));
+        assert_close(turtle.bearing().heading, Position3D::new(0.0, 0.0, 1.0));
+        assert_close(turtle.bearing().left, Position3D::new(0.0, -1.0, 0.0));
+        // a turn followed by its inverse is the identity
+        let before = turtle.bearing();
+        turtle.turn(&Bearing3D::yaw(0.3));
+        turtle.turn(&Bearing3D::yaw(0.3).flip());
+        assert_close(turtle.bearing().heading, before.heading);
+        assert_close(turtle.bearing().up, before.up);
+        turtle.move_forward(2.0);
+        assert_close(turtle.position(), Position3D::new(0.0, 0.0, 2.0));
     }
-}
 
+    #[test]
+    fn seeded_systems_replay() {
+        let mut a = LSystem::new_seeded(stochastic_rules(), vec!['X'], 7);
+        let mut b = LSystem::new_seeded(stochastic_rules(), vec!['X'], 7);
+        let first: Vec<Rc<Vec<char>>> = (0..5).map(|_| a.next().unwrap()).collect();
+        let second: Vec<Rc<Vec<char>>> = (0..5).map(|_| b.next().unwrap()).collect();
+        assert_eq!(first, second);
+
+        a.reset();
+        let replay: Vec<Rc<Vec<char>>> = (0..5).map(|_| a.next().unwrap()).collect();
+        assert_eq!(first, replay);
+    }
+
+    #[test]
+    fn lines_know_their_depth_and_distance() {
+        let drawer = PlantDrawer2D { move_distance: 2.0, ..drawer(SymbolTable::new_2d()) };
+        let input: Vec<char> = "FF[+F[-F]F]F".chars().collect();
+        let Drawing { lines, info, .. } = drawer.draw(&input, &mut Turtle2D::new(), &mut StdRng::seed_from_u64(0));
+        assert_eq!(lines.len(), info.len());
+        let depths: Vec<usize> = info.iter().map(|i| i.depth).collect();
+        let distances: Vec<f64> = info.iter().map(|i| i.distance).collect();
+        assert_eq!(depths, vec![0, 0, 1, 2, 1, 0]);
+        assert_eq!(distances, vec![0.0, 2.0, 4.0, 6.0, 6.0, 4.0]);
+        let parents: Vec<Option<usize>> = info.iter().map(|i| i.parent).collect();
+        assert_eq!(parents, vec![None, Some(0), Some(1), Some(2), Some(2), Some(1)]);
+    }
+
+    #[test]
+    fn lines_taper() {
+        let drawer = drawer(SymbolTable::new_2d());
+        let input: Vec<char> = "F[!F[!F]F]F".chars().collect();
+        let mut info = drawer.draw(&input, &mut Turtle2D::new(), &mut StdRng::seed_from_u64(0)).info;
+        let widths: Vec<f64> = info.iter().map(|i| i.width).collect();
+        assert_eq!(widths, vec![1.0, 0.75, 0.5, 0.75, 1.0]);
+
+        // the trunk carries all three tips, and the first branch two
+        pipe_model(&mut info, 2.0);
+        let tip = 3f64.sqrt().recip();
+        let expected = [1.0, 2f64.sqrt() * tip, tip, tip, tip];
+        assert_eq!(info.len(), expected.len());
+        for (line, width) in info.iter().zip(expected.iter()) {
+            assert!((line.width - width).abs() < 1e-12, "{} != {}", line.width, width);
+        }
+    }
+
+    #[test]
+    fn lines_change_colour() {
+        let input: Vec<char> = "F'F['F]F''F".chars().collect();
+        let drawer = drawer(SymbolTable::new_2d());
+        let info = drawer.draw(&input, &mut Turtle2D::new(), &mut StdRng::seed_from_u64(0)).info;
+        let colors: Vec<usize> = info.iter().map(|i| i.color).collect();
+        assert_eq!(colors, vec![0, 1, 2, 1, 3]);
+
+        // the 3D drawer counts colours the same way
+        let drawer = PlantDrawer3D {
+            move_distance: 1.0,
+            angle: 0.5,
+            width_decrement: 0.25,
+            symbols: SymbolTable::new_3d(),
+        };
+        let info = drawer.draw(&input, &mut Turtle3D::new(), &mut StdRng::seed_from_u64(0)).info;
+        let colors: Vec<usize> = info.iter().map(|i| i.color).collect();
+        assert_eq!(colors, vec![0, 1, 2, 1, 3]);
+    }
+
+    #[test]
+    fn traces_polygons() {
+        let drawer = PlantDrawer2D { move_bearing: Bearing2D { rotation: std::f64::consts::FRAC_PI_2 }, ..drawer(SymbolTable::new_2d()) };
+        // a unit square leaf at the end of a stem, and a degenerate one
+        let input: Vec<char> = "F'{.G+.G+.G+.}{.G.}".chars().collect();
+        let drawing = drawer.draw(&input, &mut Turtle2D::new(), &mut StdRng::seed_from_u64(0));
+        assert_eq!(drawing.lines.len(), 1);
+        assert_eq!(drawing.polygons.len(), 1);
+        let leaf = &drawing.polygons[0];
+        assert_eq!((leaf.color, leaf.parent, leaf.points.len()), (1, Some(0), 4));
+        assert_eq!(leaf.points[0], drawing.lines[0].end);
+        let p = &leaf.points;
+        let area: f64 = (0..4).map(|i| p[i].x * p[(i + 1) % 4].y - p[(i + 1) % 4].x * p[i].y).sum::<f64>() / 2.0;
+        assert!((area.abs() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tropism_bends_towards_its_direction() {
+        let down = Tropism { direction: Position2D { x: 0.0, y: 1.0 }, susceptibility: 0.1 };
+        let mut turtle = Turtle2D::new();
+        turtle.set_tropism(Some(down));
+        turtle.move_forward(1.0);
+        // the step itself follows the old heading
+        assert_eq!(turtle.position(), Position2D { x: 1.0, y: 0.0 });
+        assert!((turtle.bearing().rotation - 0.1).abs() < 1e-12);
+        // bending slows as the heading comes round, and never overshoots
+        for _ in 0..500 {
+            turtle.move_forward(1.0);
+            assert!(turtle.bearing().rotation < std::f64::consts::FRAC_PI_2);
+        }
+        assert!((turtle.bearing().rotation - std::f64::consts::FRAC_PI_2).abs() < 1e-6);
+
+        let mut turtle = Turtle3D::new();
+        turtle.set_tropism(Some(Tropism { direction: Position3D::new(1.0, 0.0, 0.0), susceptibility: 0.1 }));
+        turtle.move_forward(1.0);
+        let b = turtle.bearing();
+        assert_close(b.heading, Position3D::new(0.1f64.sin(), 0.1f64.cos(), 0.0));
+        // the frame stays orthonormal
+        assert!(b.heading.dot(&b.left).abs() < 1e-12 && b.heading.dot(&b.up).abs() < 1e-12);
+        assert!((b.left.length() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn places_markers_by_symbol() {
+        let mut symbols = SymbolTable::new_2d();
+        symbols.bind_markers("B bud, R fruit, Q 7").unwrap();
+        let drawer = drawer(symbols);
+        let input: Vec<char> = "F'B+FR[M]Q".chars().collect();
+        let markers = drawer.draw(&input, &mut Turtle2D::new(), &mut StdRng::seed_from_u64(0)).markers;
+        let kinds: Vec<MarkerKind> = markers.iter().map(|m| m.kind).collect();
+        assert_eq!(kinds, vec![MarkerKind::Bud, MarkerKind::Fruit, MarkerKind::Flower, MarkerKind::Custom(7)]);
+        assert_eq!(markers[0].position, Position2D { x: 1.0, y: 0.0 });
+        assert_eq!((markers[1].bearing.rotation, markers[1].scale, markers[1].color), (0.5, 1.0, Some(1)));
+
+        assert!(SymbolTable::new_2d().bind_markers("B bud berry").is_err());
+        assert!(SymbolTable::new_2d().bind_markers("BB bud").is_err());
+        assert_eq!(SymbolTable::new_2d().bind_markers("G forward").unwrap_err(), "expected a symbol and a marker kind, found `G forward`");
+    }
+
+    #[test]
+    fn draws_symbols_by_their_bindings() {
+        let mut symbols = SymbolTable::new_2d();
+        symbols.bind_all("A forward, B forward, | turn-around, F ignore").unwrap();
+        let drawer = drawer(symbols);
+        // `F` now steers nothing, and `|` sends `B` back over `A`
+        let input: Vec<char> = "AF|B".chars().collect();
+        let lines = drawer.draw(&input, &mut Turtle2D::new(), &mut StdRng::seed_from_u64(0)).lines;
+        assert_eq!(lines.len(), 2);
+        assert!((lines[1].end.x).abs() < 1e-12 && (lines[1].end.y).abs() < 1e-12);
+
+        let mut symbols = SymbolTable::empty();
+        assert_eq!(symbols.bind_all("G forward, X jump").unwrap_err(), "expected a symbol and an action, found `X jump`");
+        assert_eq!(symbols.action('G'), Some(Action::Forward));
+        assert_eq!(symbols.action('X'), None);
+    }
+}
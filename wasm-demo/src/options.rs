@@ -0,0 +1,288 @@
+use std::f64::consts::PI;
+use wasm_bindgen::prelude::*;
+use crate::animation::GrowthOrder;
+use crate::grammar::{Grammar, GrammarError};
+use crate::lsystem::{Bearing2D, MarkerKind, PlantDrawer2D, Position2D, Tropism, Turtle2D};
+use crate::scene::{self, FitMode, MarkerPainters, Style};
+use crate::shapes::FlowerSpec;
+
+
+/// Everything that shapes and colours a 2D plant.  `new()` gives the plants
+/// the site has always drawn, and each field can be changed from JavaScript
+/// before passing the options to `plant_2d_with_options`.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlantOptions {
+    pub axiom: String,
+    /// productions in the syntax of `Grammar::parse`, such as
+    /// `X -> F[+X]-X : 0.9; F -> FF`.  Symbols bound by `markers:` and
+    /// `bind:` are kept, with `markers` and `bindings` below on top of them;
+    /// settings other than those and `selection` are ignored in favour of
+    /// the fields below.
+    pub rules: String,
+    /// distance moved by each `F`
+    pub step: f64,
+    /// the step varies by up to this much from plant to plant
+    pub step_jitter: f64,
+    /// turning angle in degrees
+    pub angle: f64,
+    /// the angle varies by up to this many degrees from plant to plant
+    pub angle_jitter: f64,
+    pub order: GrowthOrder,
+    /// how much each `!` narrows the lines after it
+    pub width_decrement: f64,
+    /// whether line widths follow the pipe model rather than `!`
+    pub pipe_model: bool,
+    /// the exponent of the pipe model, where 2 is da Vinci's rule
+    pub pipe_exponent: f64,
+    /// the direction branches bend towards, in canvas coordinates so that
+    /// (0, 1) is gravity and (1, 0) a wind from the left
+    pub tropism_x: f64,
+    pub tropism_y: f64,
+    /// how strongly branches bend towards the tropism, where 0 leaves them
+    /// straight
+    pub susceptibility: f64,
+    /// the symbols that place markers, as in `B bud, R fruit`, on top of
+    /// `M` for flowers
+    pub markers: String,
+    /// symbols bound to drawing actions, as in `G forward, | turn-around`,
+    /// on top of the usual ones
+    pub bindings: String,
+
+    pub sky_color: String,
+    pub floor_color: String,
+    pub pot_color: String,
+    pub stem_color: String,
+    pub petal_color: String,
+    pub pollen_color: String,
+    pub fruit_color: String,
+    /// colours picked by `'`, separated by spaces or commas
+    pub palette: String,
+    pub line_width: f64,
+    pub flower_radius: f64,
+    /// petals in each whorl of a flower
+    pub petals: u32,
+    /// how wide each petal curves, as a fraction of its share of the whorl
+    pub petal_width: f64,
+    /// turns every flower by this many degrees
+    pub flower_rotation: f64,
+    /// rings of petals in a flower, each smaller than the one around it
+    pub whorls: u32,
+    pub whorl_scale: f64,
+    /// the colour the inner whorls shade towards, or empty to keep them in
+    /// `petal_color`
+    pub inner_petal_color: String,
+    pub floor_height: f64,
+    pub pot_height: f64,
+    pub pot_rim_width: f64,
+    pub pot_rim_height: f64,
+    pub pot_top_width: f64,
+    pub pot_bottom_width: f64,
+    /// whether the plant is rooted in a pot, or centred on the canvas
+    pub potted: bool,
+    pub fit: FitMode,
+    /// the scale used when `fit` is `FitMode::Fixed`
+    pub scale: f64,
+    pub margin: f64,
+}
+
+impl Default for PlantOptions {
+    fn default() -> Self {
+        PlantOptions::new()
+    }
+}
+
+#[wasm_bindgen]
+impl PlantOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> PlantOptions {
+        let style = Style::default();
+        let flower = FlowerSpec::default();
+        PlantOptions {
+            axiom: "X".to_string(),
+            rules: "X -> F+[[X]-X]-F[-FX]+X : 0.9\nX -> M : 0.025\nX -> X : 0.075\nF -> FF".to_string(),
+            step: 5.0,
+            step_jitter: 1.0,
+            angle: 30.0,
+            angle_jitter: (0.5 / PI).to_degrees(),
+            order: GrowthOrder::Drawing,
+            width_decrement: 0.2,
+            pipe_model: false,
+            pipe_exponent: 2.0,
+            tropism_x: 0.0,
+            tropism_y: 1.0,
+            susceptibility: 0.0,
+            markers: String::new(),
+            bindings: String::new(),
+
+            sky_color: style.sky_color,
+            floor_color: style.floor_color,
+            pot_color: style.pot_color,
+            stem_color: style.stem_color,
+            petal_color: style.petal_color,
+            pollen_color: style.pollen_color,
+            fruit_color: style.fruit_color,
+            palette: style.palette.join(" "),
+            line_width: style.line_width,
+            flower_radius: style.flower_radius,
+            petals: flower.petals,
+            petal_width: flower.petal_width,
+            flower_rotation: flower.rotation.to_degrees(),
+            whorls: flower.whorls,
+            whorl_scale: flower.whorl_scale,
+            inner_petal_color: String::new(),
+            floor_height: style.floor_height,
+            pot_height: style.pot_height,
+            pot_rim_width: style.pot_rim_width,
+            pot_rim_height: style.pot_rim_height,
+            pot_top_width: style.pot_top_width,
+            pot_bottom_width: style.pot_bottom_width,
+            potted: style.potted,
+            fit: style.fit,
+            scale: style.scale,
+            margin: style.margin,
+        }
+    }
+}
+
+impl PlantOptions {
+    /// the grammar made of the axiom and rules, with the markers and then
+    /// the bindings bound on top of the symbols the rules bind.  Errors
+    /// point into `rules`, and errors in the axiom, markers or bindings
+    /// point at line 1, column 1.
+    pub fn grammar(&self) -> Result<Grammar, GrammarError> {
+        let axiom: String = self.axiom.chars().filter(|c| !c.is_whitespace()).collect();
+        if axiom.is_empty() {
+            return Err(GrammarError { line: 1, column: 1, message: "the axiom must not be empty".to_string() });
+        }
+        let mut grammar = Grammar::parse(&format!("{}\naxiom: {}", self.rules, axiom))?;
+        grammar.symbols.bind_markers(&self.markers)
+            .and_then(|_| grammar.symbols.bind_all(&self.bindings))
+            .map_err(|message| GrammarError { line: 1, column: 1, message })?;
+        Ok(grammar)
+    }
+
+    /// a turtle at the root heading up the canvas, bent by the tropism
+    pub fn turtle(&self) -> Turtle2D {
+        let mut turtle = Turtle2D::new_from(0.0, 0.0, -PI / 2.0);
+        if self.susceptibility != 0.0 {
+            turtle.set_tropism(Some(Tropism {
+                direction: Position2D { x: self.tropism_x, y: self.tropism_y },
+                susceptibility: self.susceptibility,
+            }));
+        }
+        turtle
+    }
+
+    /// a drawer for the symbols of `grammar`, as made by `grammar()`, with
+    /// the step and angle each moved by up to their jitter, given two
+    /// uniform samples from [0, 1)
+    pub fn drawer(&self, grammar: &Grammar, step_sample: f64, angle_sample: f64) -> PlantDrawer2D<Turtle2D> {
+        PlantDrawer2D {
+            move_distance: self.step + (step_sample * 2.0 - 1.0) * self.step_jitter,
+            move_bearing: Bearing2D {
+                rotation: (self.angle + (angle_sample * 2.0 - 1.0) * self.angle_jitter).to_radians(),
+            },
+            width_decrement: self.width_decrement,
+            symbols: grammar.symbols.clone(),
+        }
+    }
+
+    /// the shape of the flowers
+    pub fn flower(&self) -> FlowerSpec {
+        let inner_color = scene::parse_palette(&self.inner_petal_color).into_iter().next();
+        FlowerSpec {
+            petals: self.petals,
+            petal_width: self.petal_width,
+            rotation: self.flower_rotation.to_radians(),
+            whorls: self.whorls,
+            whorl_scale: self.whorl_scale,
+            inner_color,
+            ..FlowerSpec::default()
+        }
+    }
+
+    /// the default painters, with flowers shaped by `flower()`
+    pub fn painters(&self) -> MarkerPainters {
+        let mut painters = MarkerPainters::default();
+        painters.set(MarkerKind::Flower, self.flower());
+        painters
+    }
+
+    pub fn style(&self) -> Style {
+        Style {
+            sky_color: self.sky_color.clone(),
+            floor_color: self.floor_color.clone(),
+            pot_color: self.pot_color.clone(),
+            stem_color: self.stem_color.clone(),
+            petal_color: self.petal_color.clone(),
+            pollen_color: self.pollen_color.clone(),
+            fruit_color: self.fruit_color.clone(),
+            palette: scene::parse_palette(&self.palette),
+            line_width: self.line_width,
+            flower_radius: self.flower_radius,
+            floor_height: self.floor_height,
+            pot_height: self.pot_height,
+            pot_rim_width: self.pot_rim_width,
+            pot_rim_height: self.pot_rim_height,
+            pot_top_width: self.pot_top_width,
+            pot_bottom_width: self.pot_bottom_width,
+            potted: self.potted,
+            fit: self.fit,
+            scale: self.scale,
+            margin: self.margin,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsystem::Action;
+
+    #[test]
+    fn defaults_match_the_site() {
+        let options = PlantOptions::new();
+        assert_eq!(options.style(), Style::default());
+        assert_eq!(options.flower(), FlowerSpec::default());
+        let grammar = options.grammar().unwrap();
+        assert_eq!(grammar.axiom, vec!['X']);
+
+        let drawer = options.drawer(&grammar, 0.5, 0.5);
+        assert_eq!(drawer.move_distance, 5.0);
+        assert!((drawer.move_bearing.rotation - PI / 6.0).abs() < 1e-12);
+        // the angle jitter is the same 1 / 2π radians as always
+        let drawer = options.drawer(&grammar, 1.0, 1.0);
+        assert!((drawer.move_bearing.rotation - (PI / 6.0 + 0.5 / PI)).abs() < 1e-12);
+
+        let mut broken = options.clone();
+        broken.rules = "X -> F : often".to_string();
+        assert_eq!(broken.grammar().unwrap_err().line, 1);
+        let mut broken = options.clone();
+        broken.markers = "B bud, R berry".to_string();
+        assert_eq!(broken.grammar().unwrap_err().message, "expected a symbol and a marker kind, found `R berry`");
+        let mut broken = options.clone();
+        broken.bindings = "G fly".to_string();
+        assert_eq!(broken.grammar().unwrap_err().message, "expected a symbol and an action, found `G fly`");
+    }
+
+    #[test]
+    fn keeps_symbols_bound_in_the_rules() {
+        let mut options = PlantOptions::new();
+        options.rules = "X -> F[+X]BX\nmarkers: B bud\nbind: A forward, G forward".to_string();
+        options.markers = "R fruit".to_string();
+        options.bindings = "G ignore".to_string();
+        let grammar = options.grammar().unwrap();
+        let symbols = &options.drawer(&grammar, 0.5, 0.5).symbols;
+        assert_eq!(symbols.action('B'), Some(Action::Marker(MarkerKind::Bud)));
+        assert_eq!(symbols.action('R'), Some(Action::Marker(MarkerKind::Fruit)));
+        assert_eq!(symbols.action('A'), Some(Action::Forward));
+        // the options' bindings win over the rules'
+        assert_eq!(symbols.action('G'), Some(Action::Ignore));
+
+        options.rules = "X -> FX\nbind: G fly".to_string();
+        let error = options.grammar().unwrap_err();
+        assert_eq!((error.line, error.message.as_str()), (2, "expected a symbol and an action, found `G fly`"));
+    }
+}
@@ -0,0 +1,590 @@
+use std::collections::HashMap;
+use std::fmt;
+use rand::prelude::*;
+use crate::lsystem::{LRules, Selection, Symbol, WeightError};
+
+
+/// A parametric module such as `F(1.5, 2)`: a symbol carrying zero or more
+/// actual parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    pub symbol: char,
+    pub params: Vec<f64>,
+}
+
+impl Module {
+    pub fn new(symbol: char, params: Vec<f64>) -> Module {
+        Module { symbol, params }
+    }
+
+    /// a module without parameters
+    pub fn bare(symbol: char) -> Module {
+        Module { symbol, params: vec![] }
+    }
+}
+
+impl Symbol for Module {
+    fn symbol(&self) -> char {
+        self.symbol
+    }
+
+    fn param(&self, i: usize) -> Option<f64> {
+        self.params.get(i).copied()
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbol)?;
+        if !self.params.is_empty() {
+            let params: Vec<String> = self.params.iter().map(|p| p.to_string()).collect();
+            write!(f, "({})", params.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// A convenience function to print out the String representation of a module
+/// vector.
+pub fn modules_to_string(v: &[Module]) -> String {
+    v.iter().map(|m| m.to_string()).collect()
+}
+
+
+/// An error raised while parsing a module string, production or expression.
+/// `position` is the character offset into the parsed source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(position: usize, message: impl Into<String>) -> ParseError {
+        ParseError { position, message: message.into() }
+    }
+
+    fn shifted(self, offset: usize) -> ParseError {
+        ParseError { position: self.position + offset, message: self.message }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+/// An arithmetic or logical expression over the formal parameters of a
+/// production.  Variables are resolved to the index of their formal parameter
+/// when the production is parsed; comparisons and logic evaluate to 1 or 0.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Num(f64),
+    Var(usize),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+fn truth(b: bool) -> f64 {
+    if b { 1.0 } else { 0.0 }
+}
+
+impl Expr {
+    /// evaluate the expression with `vars` bound to the formal parameters
+    pub fn eval(&self, vars: &[f64]) -> f64 {
+        match self {
+            Expr::Num(n) => *n,
+            Expr::Var(i) => vars.get(*i).copied().unwrap_or(0.0),
+            Expr::Neg(e) => -e.eval(vars),
+            Expr::Not(e) => truth(e.eval(vars) == 0.0),
+            Expr::Binary(op, l, r) => {
+                let a = l.eval(vars);
+                let b = r.eval(vars);
+                match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Sub => a - b,
+                    BinaryOp::Mul => a * b,
+                    BinaryOp::Div => a / b,
+                    BinaryOp::Pow => a.powf(b),
+                    BinaryOp::Lt => truth(a < b),
+                    BinaryOp::Le => truth(a <= b),
+                    BinaryOp::Gt => truth(a > b),
+                    BinaryOp::Ge => truth(a >= b),
+                    BinaryOp::Eq => truth(a == b),
+                    BinaryOp::Ne => truth(a != b),
+                    BinaryOp::And => truth(a != 0.0 && b != 0.0),
+                    BinaryOp::Or => truth(a != 0.0 || b != 0.0),
+                }
+            }
+        }
+    }
+
+    /// parse a standalone expression over the named formal parameters
+    pub fn parse(src: &str, formals: &[String]) -> Result<Expr, ParseError> {
+        let mut parser = Parser::new(src, formals);
+        let expr = parser.expr()?;
+        parser.end()?;
+        Ok(expr)
+    }
+}
+
+
+/// A module in the successor of a production, whose parameters are
+/// expressions over the predecessor's formal parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleTemplate {
+    pub symbol: char,
+    pub args: Vec<Expr>,
+}
+
+impl ModuleTemplate {
+    fn instantiate(&self, vars: &[f64]) -> Module {
+        Module {
+            symbol: self.symbol,
+            params: self.args.iter().map(|e| e.eval(vars)).collect(),
+        }
+    }
+}
+
+/// A production `A(l,w) : condition -> successor` applying to modules with
+/// the predecessor's symbol and arity.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Production {
+    pub predecessor: char,
+    pub formals: Vec<String>,
+    pub condition: Option<Expr>,
+    pub successor: Vec<ModuleTemplate>,
+    /// the production's weight, as chosen by the ruleset's `Selection`
+    pub probability: f64,
+}
+
+impl Production {
+    /// parse a production written as `A(l) : l > 1 -> F(l*0.8)[+A(l*0.6)]`,
+    /// where the condition is optional
+    pub fn parse(src: &str) -> Result<Production, ParseError> {
+        let arrow = src.find("->")
+            .ok_or_else(|| ParseError::new(0, "expected `->` in production"))?;
+        let (head, successor) = (&src[..arrow], &src[arrow + 2..]);
+        let successor_offset = src[..arrow + 2].chars().count();
+
+        let (predecessor, condition) = match head.find(':') {
+            Some(i) => (&head[..i], Some((&head[i + 1..], head[..i + 1].chars().count()))),
+            None => (head, None),
+        };
+
+        let mut parser = Parser::new(predecessor, &[]);
+        let (symbol, formals) = parser.predecessor()?;
+
+        let condition = match condition {
+            Some((c, offset)) => {
+                let mut parser = Parser::new(c, &formals);
+                let expr = parser.expr().map_err(|e| e.shifted(offset))?;
+                parser.end().map_err(|e| e.shifted(offset))?;
+                Some(expr)
+            }
+            None => None,
+        };
+
+        let mut parser = Parser::new(successor, &formals);
+        let successor = parser.templates().map_err(|e| e.shifted(successor_offset))?;
+
+        Ok(Production {
+            predecessor: symbol,
+            formals,
+            condition,
+            successor,
+            probability: 1.0,
+        })
+    }
+
+    /// whether this production applies to `module`
+    pub fn matches(&self, module: &Module) -> bool {
+        module.symbol == self.predecessor
+            && module.params.len() == self.formals.len()
+            && self.condition.as_ref().is_none_or(|c| c.eval(&module.params) != 0.0)
+    }
+
+    /// produce the successor modules for `module`
+    pub fn apply(&self, module: &Module) -> Vec<Module> {
+        self.successor.iter().map(|t| t.instantiate(&module.params)).collect()
+    }
+}
+
+/// parse a string of modules with constant parameters, such as an axiom
+/// `A(10)F(1,2)`
+pub fn parse_modules(src: &str) -> Result<Vec<Module>, ParseError> {
+    let mut parser = Parser::new(src, &[]);
+    let templates = parser.templates()?;
+    Ok(templates.iter().map(|t| t.instantiate(&[])).collect())
+}
+
+
+/// Production rules for parametric L-systems.  Several productions may share
+/// a predecessor; one is chosen among those whose arity and condition match
+/// according to the ruleset's selection policy.
+#[derive(Default)]
+pub struct ParametricRules {
+    productions: HashMap<char, Vec<Production>>,
+    selection: Selection,
+}
+
+impl ParametricRules {
+    /// Create a new, empty ruleset choosing productions by weight.
+    pub fn new() -> ParametricRules {
+        ParametricRules { productions: HashMap::new(), selection: Selection::Weighted }
+    }
+
+    /// Change the policy choosing among matching productions
+    pub fn set_selection(&mut self, selection: Selection) {
+        self.selection = selection;
+    }
+
+    /// Check that the weights of the productions competing for each module
+    /// sum to 1, where productions compete when they share a predecessor,
+    /// arity and condition.  Only rulesets using `Selection::Strict` are
+    /// checked.
+    pub fn validate(&self) -> Result<(), WeightError<char>> {
+        if self.selection != Selection::Strict {
+            return Ok(());
+        }
+        for (&atom, prods) in self.productions.iter() {
+            for p in prods.iter() {
+                let rivals = prods.iter().filter(|q| q.formals.len() == p.formals.len() && q.condition == p.condition);
+                WeightError::check(atom, rivals.map(|q| q.probability))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a production
+    pub fn add(&mut self, production: Production) {
+        self.productions.entry(production.predecessor).or_default().push(production);
+    }
+
+    /// Parse and add a production
+    pub fn add_str(&mut self, src: &str) -> Result<(), ParseError> {
+        self.add_str_prob(src, 1.0)
+    }
+
+    /// Parse and add a production with weight p
+    pub fn add_str_prob(&mut self, src: &str, p: f64) -> Result<(), ParseError> {
+        let mut production = Production::parse(src)?;
+        production.probability = p;
+        self.add(production);
+        Ok(())
+    }
+}
+
+impl LRules<Module> for ParametricRules {
+    fn map(&self, input: &Module, rng: &mut dyn RngCore) -> Option<Vec<Module>> {
+        let prods = self.productions.get(&input.symbol)?;
+        let matching: Vec<&Production> = prods.iter().filter(|p| p.matches(input)).collect();
+        if matching.is_empty() {
+            return None;
+        }
+        let weights: Vec<f64> = matching.iter().map(|p| p.probability).collect();
+        match self.selection.choose(&weights, rng) {
+            Some(i) => Some(matching[i].apply(input)),
+            None => Some(vec![input.clone()]),
+        }
+    }
+}
+
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    formals: &'a [String],
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &str, formals: &'a [String]) -> Parser<'a> {
+        Parser { chars: src.chars().collect(), pos: 0, formals }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError::new(self.pos, message)
+    }
+
+    fn skip_ws(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_ws();
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        self.skip_ws();
+        let n = s.chars().count();
+        if self.chars.len() >= self.pos + n && self.chars[self.pos..self.pos + n].iter().copied().eq(s.chars()) {
+            self.pos += n;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{}`", c)))
+        }
+    }
+
+    fn end(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(c) => Err(self.error(format!("unexpected `{}`", c))),
+        }
+    }
+
+    fn ident(&mut self) -> Option<String> {
+        self.skip_ws();
+        let start = self.pos;
+        while self.chars.get(self.pos).is_some_and(|c| c.is_alphanumeric() || *c == '_')
+            && (self.pos > start || self.chars[self.pos].is_alphabetic() || self.chars[self.pos] == '_') {
+            self.pos += 1;
+        }
+        if self.pos > start {
+            Some(self.chars[start..self.pos].iter().collect())
+        } else {
+            None
+        }
+    }
+
+    fn predecessor(&mut self) -> Result<(char, Vec<String>), ParseError> {
+        let symbol = self.peek().ok_or_else(|| self.error("expected a predecessor symbol"))?;
+        self.pos += 1;
+        let mut formals = vec![];
+        if self.eat("(") {
+            loop {
+                let name = self.ident().ok_or_else(|| self.error("expected a parameter name"))?;
+                if formals.contains(&name) {
+                    return Err(self.error(format!("duplicate parameter `{}`", name)));
+                }
+                formals.push(name);
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.expect(')')?;
+        }
+        self.end()?;
+        Ok((symbol, formals))
+    }
+
+    fn templates(&mut self) -> Result<Vec<ModuleTemplate>, ParseError> {
+        let mut out = vec![];
+        while let Some(c) = self.peek() {
+            if c == '(' || c == ')' || c == ',' {
+                return Err(self.error(format!("unexpected `{}`", c)));
+            }
+            self.pos += 1;
+            let mut args = vec![];
+            if self.chars.get(self.pos) == Some(&'(') {
+                self.pos += 1;
+                loop {
+                    args.push(self.expr()?);
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                self.expect(')')?;
+            }
+            out.push(ModuleTemplate { symbol: c, args });
+        }
+        Ok(out)
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.and()?;
+        while self.eat("||") {
+            let rhs = self.and()?;
+            lhs = Expr::Binary(BinaryOp::Or, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.comparison()?;
+        while self.eat("&&") {
+            let rhs = self.comparison()?;
+            lhs = Expr::Binary(BinaryOp::And, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let lhs = self.sum()?;
+        let ops = [
+            ("<=", BinaryOp::Le), (">=", BinaryOp::Ge), ("==", BinaryOp::Eq),
+            ("!=", BinaryOp::Ne), ("<", BinaryOp::Lt), (">", BinaryOp::Gt),
+        ];
+        for (s, op) in ops.iter() {
+            if self.eat(s) {
+                let rhs = self.sum()?;
+                return Ok(Expr::Binary(*op, Box::new(lhs), Box::new(rhs)));
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn sum(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.product()?;
+        loop {
+            let op = if self.eat("+") {
+                BinaryOp::Add
+            } else if self.eat("-") {
+                BinaryOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.product()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = if self.eat("*") {
+                BinaryOp::Mul
+            } else if self.eat("/") {
+                BinaryOp::Div
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.unary()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.eat("-") {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else if self.peek() == Some('!') && self.chars.get(self.pos + 1) != Some(&'=') {
+            self.pos += 1;
+            Ok(Expr::Not(Box::new(self.unary()?)))
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let base = self.atom()?;
+        if self.eat("^") {
+            let exponent = self.unary()?;
+            Ok(Expr::Binary(BinaryOp::Pow, Box::new(base), Box::new(exponent)))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, ParseError> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let e = self.expr()?;
+                self.expect(')')?;
+                Ok(e)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.pos;
+                while self.chars.get(self.pos).is_some_and(|c| c.is_ascii_digit() || *c == '.') {
+                    self.pos += 1;
+                }
+                let text: String = self.chars[start..self.pos].iter().collect();
+                text.parse::<f64>()
+                    .map(Expr::Num)
+                    .map_err(|_| ParseError::new(start, format!("invalid number `{}`", text)))
+            }
+            Some(_) => {
+                let start = self.pos;
+                let name = self.ident().ok_or_else(|| self.error("expected an expression"))?;
+                match self.formals.iter().position(|f| *f == name) {
+                    Some(i) => Ok(Expr::Var(i)),
+                    None => Err(ParseError::new(start, format!("unknown parameter `{}`", name))),
+                }
+            }
+            None => Err(self.error("unexpected end of expression")),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsystem::LSystem;
+
+    #[test]
+    fn evaluates_expressions() {
+        let formals = vec!["l".to_string(), "w".to_string()];
+        let e = Expr::parse("l*0.5 + w^2 - -1", &formals).unwrap();
+        assert_eq!(e.eval(&[4.0, 3.0]), 12.0);
+        let c = Expr::parse("l > 1 && !(w == 3)", &formals).unwrap();
+        assert_eq!(c.eval(&[2.0, 3.0]), 0.0);
+        assert_eq!(c.eval(&[2.0, 2.0]), 1.0);
+        assert!(Expr::parse("x + 1", &formals).is_err());
+    }
+
+    #[test]
+    fn rewrites_parametric_modules() {
+        let mut rules = ParametricRules::new();
+        rules.add_str("A(l) : l >= 1 -> F(l*0.8)[+A(l*0.5)]").unwrap();
+        let axiom = parse_modules("A(2)").unwrap();
+        let mut system = LSystem::new_seeded(rules, axiom, 0);
+        let state = system.next().unwrap();
+        assert_eq!(modules_to_string(&state), "F(1.6)[+A(1)]");
+        let state = system.next().unwrap();
+        assert_eq!(modules_to_string(&state), "F(1.6)[+F(0.8)[+A(0.5)]]");
+        // the condition no longer holds, so nothing rewrites
+        assert!(system.next().is_none());
+    }
+
+    #[test]
+    fn validates_strict_weights() {
+        let mut rules = ParametricRules::new();
+        rules.set_selection(Selection::Strict);
+        rules.add_str_prob("A(l) : l > 1 -> F(l)", 0.6).unwrap();
+        rules.add_str_prob("A(l) : l > 1 -> A(l)", 0.4).unwrap();
+        rules.add_str("A(l) : l <= 1 -> F(l)").unwrap();
+        assert_eq!(rules.validate(), Ok(()));
+        rules.add_str_prob("A(l) : l <= 1 -> B", 0.3).unwrap();
+        assert_eq!(rules.validate().unwrap_err().total, 1.3);
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        let err = Production::parse("A(l) -> F(l*)").unwrap_err();
+        assert_eq!(err.position, 12);
+    }
+}
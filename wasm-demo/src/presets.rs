@@ -0,0 +1,173 @@
+use crate::grammar::Grammar;
+use crate::scene::{FitMode, Style};
+
+
+/// A named L-system written in the syntax of `Grammar::parse`, with the
+/// angle and number of iterations it looks best at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Preset {
+    pub name: &'static str,
+    pub source: &'static str,
+    /// whether it is a plant growing from a pot rather than a figure
+    pub potted: bool,
+}
+
+impl Preset {
+    pub fn grammar(&self) -> Grammar {
+        Grammar::parse(self.source).expect("presets are valid grammars")
+    }
+
+    /// the style to draw the preset in: scaled to fit the canvas, in the
+    /// grammar's palette, and centred unless it grows from a pot
+    pub fn style(&self) -> Style {
+        Style {
+            palette: self.grammar().palette,
+            fit: FitMode::Contain,
+            potted: self.potted,
+            ..Style::default()
+        }
+    }
+}
+
+/// The built-in L-systems: the classic curves and tilings, and the plants
+/// of figures 1.24 and 1.27 of The Algorithmic Beauty of Plants.
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "quadratic-koch-curve",
+        source: "axiom: F; angle: 90; iterations: 4\nF -> F+F-F-F+F",
+        potted: false,
+    },
+    Preset {
+        name: "koch-snowflake",
+        source: "axiom: F--F--F; angle: 60; iterations: 4\nF -> F+F--F+F",
+        potted: false,
+    },
+    Preset {
+        name: "sierpinski-arrowhead",
+        source: "axiom: A; angle: 60; iterations: 6; bind: A forward, B forward\nA -> B-A-B\nB -> A+B+A",
+        potted: false,
+    },
+    Preset {
+        name: "dragon-curve",
+        source: "axiom: FX; angle: 90; iterations: 10\nX -> X+YF+\nY -> -FX-Y",
+        potted: false,
+    },
+    Preset {
+        name: "hilbert-curve",
+        source: "axiom: A; angle: 90; iterations: 5\nA -> +BF-AFA-FB+\nB -> -AF+BFB+FA-",
+        potted: false,
+    },
+    Preset {
+        name: "gosper-curve",
+        source: "axiom: A; angle: 60; iterations: 4; bind: A forward, B forward\nA -> A-B--B+A++AA+B-\nB -> +A-AA--A-B++B+A",
+        potted: false,
+    },
+    Preset {
+        // Penrose's P3 tiling of rhombs, where the digits only steer the
+        // rewriting and each `F` is an edge drawn once and then dropped
+        name: "penrose-tiling",
+        source: "axiom: [7]++[7]++[7]++[7]++[7]; angle: 36; iterations: 4\n\
+                 6 -> 8F++9F----7F[-8F----6F]++\n\
+                 7 -> +8F--9F[---6F--7F]+\n\
+                 8 -> -6F++7F[+++8F++9F]-\n\
+                 9 -> --8F++++6F[+9F++++7F]--7F\n\
+                 F ->",
+        potted: false,
+    },
+    Preset {
+        name: "plant-a",
+        source: "axiom: F; angle: 25.7; iterations: 5\nF -> F[+F]F[-F]F",
+        potted: true,
+    },
+    Preset {
+        name: "plant-b",
+        source: "axiom: F; angle: 20; iterations: 5\nF -> F[+F]F[-F][F]",
+        potted: true,
+    },
+    Preset {
+        name: "plant-c",
+        source: "axiom: F; angle: 22.5; iterations: 4\nF -> FF-[-F+F+F]+[+F-F-F]",
+        potted: true,
+    },
+    Preset {
+        name: "plant-d",
+        source: "axiom: X; angle: 20; iterations: 7\nX -> F[+X]F[-X]+X\nF -> FF",
+        potted: true,
+    },
+    Preset {
+        name: "plant-e",
+        source: "axiom: X; angle: 25.7; iterations: 7\nX -> F[+X][-X]FX\nF -> FF",
+        potted: true,
+    },
+    Preset {
+        name: "plant-f",
+        source: "axiom: X; angle: 22.5; iterations: 5\nX -> F-[[X]+X]+F[+FX]-X\nF -> FF",
+        potted: true,
+    },
+    Preset {
+        name: "stochastic-bush",
+        source: "axiom: F; angle: 25.7; iterations: 5\n\
+                 F -> F[+F]F[-F]F : 0.33\n\
+                 F -> F[+F]F : 0.33\n\
+                 F -> F[-F]F : 0.34",
+        potted: true,
+    },
+    Preset {
+        name: "flowering-bush",
+        source: "axiom: X; angle: 30; iterations: 5\n\
+                 X -> F+[[X]-X]-F[-FX]+X : 0.9\n\
+                 X -> M : 0.025\n\
+                 X -> X : 0.075\n\
+                 F -> FF",
+        potted: true,
+    },
+];
+
+/// the preset called `name`
+pub fn preset(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|p| p.name == name)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_plant_from_grammar;
+    use crate::scene::Bounds;
+
+    #[test]
+    fn every_preset_draws() {
+        for preset in PRESETS {
+            let grammar = preset.grammar();
+            let plant = generate_plant_from_grammar(&grammar, 1, grammar.iterations.min(3));
+            assert!(!plant.lines.is_empty(), "{} draws nothing", preset.name);
+            assert_eq!(PRESETS.iter().filter(|p| p.name == preset.name).count(), 1);
+        }
+        assert_eq!(preset("plant-e").map(|p| p.grammar().angle), Some(25.7));
+        assert_eq!(preset("fern"), None);
+    }
+
+    #[test]
+    fn every_preset_stays_inside_the_canvas() {
+        for preset in PRESETS {
+            let grammar = preset.grammar();
+            let plant = generate_plant_from_grammar(&grammar, 1, grammar.iterations);
+            let bounds = Bounds::of(&plant).unwrap();
+            // fitted to a canvas, and at its own size on an image sized for it
+            let fitted = preset.style();
+            let fixed = Style { fit: FitMode::Fixed, ..preset.style() };
+            for (style, (width, height)) in [(fitted, (400.0, 300.0)), (fixed.clone(), fixed.plant_size(&plant))] {
+                let transform = style.plant_transform(&plant, width, height);
+                let (min, max) = (transform.apply(bounds.min), transform.apply(bounds.max));
+                let floor = height - style.floor_height;
+                let inside = min.x >= style.margin - 1e-9 && max.x <= width - style.margin + 1e-9
+                    && min.y >= style.margin - 1e-9 && max.y <= floor + 1e-9;
+                assert!(inside, "{} spans {:?} to {:?} on {}x{}", preset.name, min, max, width, height);
+                if !preset.potted {
+                    let centre = ((min.x + max.x) / 2.0, (min.y + max.y) / 2.0);
+                    assert!((centre.0 - width / 2.0).abs() < 1e-6 && (centre.1 - floor / 2.0).abs() < 1e-6, "{} is off centre", preset.name);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use std::f64::consts::PI;
+use crate::render::Renderer;
+
+
+/// parse a `#rgb` or `#rrggbb` colour, falling back to black
+fn parse_color(color: &str) -> [u8; 3] {
+    let hex = color.trim_start_matches('#');
+    let digits: Vec<u8> = hex.chars().filter_map(|c| c.to_digit(16)).map(|d| d as u8).collect();
+    match (hex.len(), digits.len()) {
+        (3, 3) => [digits[0] * 17, digits[1] * 17, digits[2] * 17],
+        (6, 6) => [
+            digits[0] * 16 + digits[1],
+            digits[2] * 16 + digits[3],
+            digits[4] * 16 + digits[5],
+        ],
+        _ => [0, 0, 0],
+    }
+}
+
+/// A polyline of the current path; `close_path` ends it where it began.
+#[derive(Clone, Debug, Default)]
+struct Subpath {
+    points: Vec<(f64, f64)>,
+}
+
+/// Renders into an in-memory RGB image, so plants can be written out as PPM
+/// or PNG files without a browser.  Curves are flattened into short line
+/// segments and nothing is antialiased.
+pub struct RasterRenderer {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 3]>,
+    fill: [u8; 3],
+    stroke: [u8; 3],
+    line_width: f64,
+    path: Vec<Subpath>,
+}
+
+impl RasterRenderer {
+    /// a white image of the given size in pixels
+    pub fn new(width: usize, height: usize) -> RasterRenderer {
+        RasterRenderer {
+            width,
+            height,
+            pixels: vec![[255, 255, 255]; width * height],
+            fill: [0, 0, 0],
+            stroke: [0, 0, 0],
+            line_width: 1.0,
+            path: vec![],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// the colour of the pixel at (x, y)
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        self.pixels[y * self.width + x]
+    }
+
+    /// the image as a binary PPM (P6) file
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut out = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        out.extend(self.pixels.iter().flatten());
+        out
+    }
+
+    /// the image as an 8-bit RGB PNG file
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks(self.width.max(1)).take(self.height) {
+            // each scanline starts with its filter type, here always none
+            raw.push(0);
+            raw.extend(row.iter().flatten());
+        }
+        encode_png(self.width as u32, self.height as u32, &raw)
+    }
+
+    fn last_point(&self) -> Option<(f64, f64)> {
+        self.path.last().and_then(|s| s.points.last().copied())
+    }
+
+    fn plot(&mut self, x: i64, y: i64, color: [u8; 3]) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            self.pixels[y as usize * self.width + x as usize] = color;
+        }
+    }
+
+    /// fill every pixel whose centre lies within `radius` of the segment
+    /// from `a` to `b`, giving round caps and joins
+    fn stroke_segment(&mut self, a: (f64, f64), b: (f64, f64), radius: f64) {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let length2 = dx * dx + dy * dy;
+        let x0 = (a.0.min(b.0) - radius).floor().max(0.0) as i64;
+        let x1 = (a.0.max(b.0) + radius).ceil().min(self.width as f64) as i64;
+        let y0 = (a.1.min(b.1) - radius).floor().max(0.0) as i64;
+        let y1 = (a.1.max(b.1) + radius).ceil().min(self.height as f64) as i64;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+                let t = if length2 > 0.0 {
+                    (((px - a.0) * dx + (py - a.1) * dy) / length2).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let (ex, ey) = (a.0 + t * dx - px, a.1 + t * dy - py);
+                if ex * ex + ey * ey <= radius * radius {
+                    self.plot(x, y, self.stroke);
+                }
+            }
+        }
+    }
+}
+
+impl Renderer for RasterRenderer {
+    fn set_fill_style(&mut self, color: &str) {
+        self.fill = parse_color(color);
+    }
+
+    fn set_stroke_style(&mut self, color: &str) {
+        self.stroke = parse_color(color);
+    }
+
+    fn set_line_width(&mut self, width: f64) {
+        self.line_width = width;
+    }
+
+    fn begin_path(&mut self) {
+        self.path.clear();
+    }
+
+    fn move_to(&mut self, x: f64, y: f64) {
+        self.path.push(Subpath { points: vec![(x, y)] });
+    }
+
+    fn line_to(&mut self, x: f64, y: f64) {
+        match self.path.last_mut() {
+            Some(subpath) => subpath.points.push((x, y)),
+            None => self.move_to(x, y),
+        }
+    }
+
+    fn bezier_curve_to(&mut self, cp1x: f64, cp1y: f64, cp2x: f64, cp2y: f64, x: f64, y: f64) {
+        let (x0, y0) = match self.last_point() {
+            Some(p) => p,
+            None => {
+                self.move_to(cp1x, cp1y);
+                (cp1x, cp1y)
+            }
+        };
+        let steps = 16;
+        for i in 1..=steps {
+            let t = f64::from(i) / f64::from(steps);
+            let u = 1.0 - t;
+            let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+            self.line_to(
+                a * x0 + b * cp1x + c * cp2x + d * x,
+                a * y0 + b * cp1y + c * cp2y + d * y,
+            );
+        }
+    }
+
+    fn arc(&mut self, x: f64, y: f64, radius: f64, start_angle: f64, end_angle: f64) {
+        let sweep = end_angle - start_angle;
+        let sweep = if sweep >= 2.0 * PI { 2.0 * PI } else { sweep.rem_euclid(2.0 * PI) };
+        let steps = ((radius * sweep).ceil() as usize).clamp(4, 256);
+        for i in 0..=steps {
+            let angle = start_angle + sweep * i as f64 / steps as f64;
+            self.line_to(x + radius * angle.cos(), y + radius * angle.sin());
+        }
+    }
+
+    fn close_path(&mut self) {
+        if let Some(subpath) = self.path.last_mut() {
+            if let Some(&first) = subpath.points.first() {
+                subpath.points.push(first);
+                self.path.push(Subpath { points: vec![first] });
+            }
+        }
+    }
+
+    /// fill the current path with the nonzero winding rule, sampling each
+    /// pixel at its centre
+    fn fill(&mut self) {
+        let mut edges = vec![];
+        for subpath in self.path.iter() {
+            let points = &subpath.points;
+            if points.len() < 2 {
+                continue;
+            }
+            // every subpath is implicitly closed for filling
+            for i in 0..points.len() {
+                edges.push((points[i], points[(i + 1) % points.len()]));
+            }
+        }
+
+        let mut crossings: Vec<(f64, i32)> = vec![];
+        for y in 0..self.height {
+            let py = y as f64 + 0.5;
+            crossings.clear();
+            for &(a, b) in edges.iter() {
+                if (a.1 <= py) != (b.1 <= py) {
+                    let x = a.0 + (py - a.1) / (b.1 - a.1) * (b.0 - a.0);
+                    crossings.push((x, if b.1 > a.1 { 1 } else { -1 }));
+                }
+            }
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut winding = 0;
+            for pair in crossings.windows(2) {
+                winding += pair[0].1;
+                if winding != 0 {
+                    let x0 = (pair[0].0 - 0.5).ceil().max(0.0) as i64;
+                    let x1 = (pair[1].0 - 0.5).ceil().min(self.width as f64) as i64;
+                    for x in x0..x1 {
+                        self.plot(x, y as i64, self.fill);
+                    }
+                }
+            }
+        }
+    }
+
+    fn stroke(&mut self) {
+        let radius = self.line_width / 2.0;
+        let segments: Vec<_> = self.path.iter()
+            .flat_map(|s| s.points.windows(2).map(|w| (w[0], w[1])))
+            .collect();
+        for (a, b) in segments {
+            self.stroke_segment(a, b, radius);
+        }
+    }
+
+    fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        let x0 = (x - 0.5).ceil().max(0.0) as i64;
+        let x1 = (x + width - 0.5).ceil().min(self.width as f64) as i64;
+        let y0 = (y - 0.5).ceil().max(0.0) as i64;
+        let y1 = (y + height - 0.5).ceil().min(self.height as f64) as i64;
+        for py in y0..y1 {
+            for px in x0..x1 {
+                self.plot(px, py, self.fill);
+            }
+        }
+    }
+}
+
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+/// Encode filtered RGB scanlines as a PNG.  The image data is stored in
+/// uncompressed deflate blocks, which keeps the encoder tiny at the cost of
+/// file size.
+fn encode_png(width: u32, height: u32, scanlines: &[u8]) -> Vec<u8> {
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+
+    let mut header = vec![];
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // 8 bits per channel, truecolour, default compression, filtering and no
+    // interlacing
+    header.extend([8, 2, 0, 0, 0]);
+    png_chunk(&mut out, b"IHDR", &header);
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = scanlines.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        zlib.push(u8::from(last));
+        zlib.extend(len.to_le_bytes());
+        zlib.extend((!len).to_le_bytes());
+        zlib.extend(block);
+    }
+    zlib.extend(adler32(scanlines).to_be_bytes());
+    png_chunk(&mut out, b"IDAT", &zlib);
+
+    png_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(parse_color("#4F7942"), [0x4F, 0x79, 0x42]);
+        assert_eq!(parse_color("#fff"), [255, 255, 255]);
+    }
+
+    #[test]
+    fn fills_and_strokes_paths() {
+        let mut r = RasterRenderer::new(20, 20);
+        r.set_fill_style("#ff0000");
+        r.begin_path();
+        r.move_to(2.0, 2.0);
+        r.line_to(10.0, 2.0);
+        r.line_to(10.0, 10.0);
+        r.line_to(2.0, 10.0);
+        r.close_path();
+        r.fill();
+        assert_eq!(r.pixel(5, 5), [255, 0, 0]);
+        assert_eq!(r.pixel(12, 5), [255, 255, 255]);
+
+        r.set_stroke_style("#00f");
+        r.set_line_width(2.0);
+        r.begin_path();
+        r.move_to(15.0, 0.0);
+        r.line_to(15.0, 20.0);
+        r.stroke();
+        assert_eq!(r.pixel(15, 12), [0, 0, 255]);
+        assert_eq!(r.pixel(17, 12), [255, 255, 255]);
+
+        let png = r.to_png();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+    }
+}
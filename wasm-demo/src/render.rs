@@ -0,0 +1,203 @@
+/// A 2D drawing surface modelled on the Canvas2D path API, so scenes can be
+/// drawn to a canvas, an SVG document or a recording without knowing which.
+pub trait Renderer {
+    /// set the colour used by `fill` and `fill_rect`
+    fn set_fill_style(&mut self, color: &str);
+
+    /// set the colour used by `stroke`
+    fn set_stroke_style(&mut self, color: &str);
+
+    /// set the width of stroked lines
+    fn set_line_width(&mut self, width: f64);
+
+    /// start a new, empty path
+    fn begin_path(&mut self);
+
+    fn move_to(&mut self, x: f64, y: f64);
+
+    fn line_to(&mut self, x: f64, y: f64);
+
+    fn bezier_curve_to(&mut self, cp1x: f64, cp1y: f64, cp2x: f64, cp2y: f64, x: f64, y: f64);
+
+    /// add a clockwise arc around (x, y) between two angles in radians
+    fn arc(&mut self, x: f64, y: f64, radius: f64, start_angle: f64, end_angle: f64);
+
+    fn close_path(&mut self);
+
+    /// fill the current path
+    fn fill(&mut self);
+
+    /// stroke the current path
+    fn stroke(&mut self);
+
+    /// fill a rectangle without touching the current path
+    fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64);
+}
+
+
+/// Draws onto a Canvas2D context.
+pub struct CanvasRenderer {
+    ctx: web_sys::CanvasRenderingContext2d,
+}
+
+impl CanvasRenderer {
+    pub fn new(ctx: web_sys::CanvasRenderingContext2d) -> CanvasRenderer {
+        CanvasRenderer { ctx }
+    }
+
+    /// the canvas width and height
+    pub fn size(&self) -> (f64, f64) {
+        let canvas = self.ctx.canvas().unwrap();
+        (f64::from(canvas.width()), f64::from(canvas.height()))
+    }
+}
+
+impl Renderer for CanvasRenderer {
+    fn set_fill_style(&mut self, color: &str) {
+        self.ctx.set_fill_style_str(color);
+    }
+
+    fn set_stroke_style(&mut self, color: &str) {
+        self.ctx.set_stroke_style_str(color);
+    }
+
+    fn set_line_width(&mut self, width: f64) {
+        self.ctx.set_line_width(width);
+    }
+
+    fn begin_path(&mut self) {
+        self.ctx.begin_path();
+    }
+
+    fn move_to(&mut self, x: f64, y: f64) {
+        self.ctx.move_to(x, y);
+    }
+
+    fn line_to(&mut self, x: f64, y: f64) {
+        self.ctx.line_to(x, y);
+    }
+
+    fn bezier_curve_to(&mut self, cp1x: f64, cp1y: f64, cp2x: f64, cp2y: f64, x: f64, y: f64) {
+        self.ctx.bezier_curve_to(cp1x, cp1y, cp2x, cp2y, x, y);
+    }
+
+    fn arc(&mut self, x: f64, y: f64, radius: f64, start_angle: f64, end_angle: f64) {
+        self.ctx.arc(x, y, radius, start_angle, end_angle).unwrap();
+    }
+
+    fn close_path(&mut self) {
+        self.ctx.close_path();
+    }
+
+    fn fill(&mut self) {
+        self.ctx.fill();
+    }
+
+    fn stroke(&mut self) {
+        self.ctx.stroke();
+    }
+
+    fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        self.ctx.fill_rect(x, y, width, height);
+    }
+}
+
+
+/// A call made on a `RecordingRenderer`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrawCommand {
+    SetFillStyle(String),
+    SetStrokeStyle(String),
+    SetLineWidth(f64),
+    BeginPath,
+    MoveTo(f64, f64),
+    LineTo(f64, f64),
+    BezierCurveTo(f64, f64, f64, f64, f64, f64),
+    Arc(f64, f64, f64, f64, f64),
+    ClosePath,
+    Fill,
+    Stroke,
+    FillRect(f64, f64, f64, f64),
+}
+
+/// Records every call made on it, for tests and for replaying drawings.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecordingRenderer {
+    pub commands: Vec<DrawCommand>,
+}
+
+impl RecordingRenderer {
+    pub fn new() -> RecordingRenderer {
+        RecordingRenderer { commands: vec![] }
+    }
+
+    /// replay the recorded calls onto another renderer
+    pub fn replay(&self, r: &mut dyn Renderer) {
+        for command in self.commands.iter() {
+            match command {
+                DrawCommand::SetFillStyle(c) => r.set_fill_style(c),
+                DrawCommand::SetStrokeStyle(c) => r.set_stroke_style(c),
+                DrawCommand::SetLineWidth(w) => r.set_line_width(*w),
+                DrawCommand::BeginPath => r.begin_path(),
+                DrawCommand::MoveTo(x, y) => r.move_to(*x, *y),
+                DrawCommand::LineTo(x, y) => r.line_to(*x, *y),
+                DrawCommand::BezierCurveTo(a, b, c, d, x, y) => r.bezier_curve_to(*a, *b, *c, *d, *x, *y),
+                DrawCommand::Arc(x, y, radius, s, e) => r.arc(*x, *y, *radius, *s, *e),
+                DrawCommand::ClosePath => r.close_path(),
+                DrawCommand::Fill => r.fill(),
+                DrawCommand::Stroke => r.stroke(),
+                DrawCommand::FillRect(x, y, w, h) => r.fill_rect(*x, *y, *w, *h),
+            }
+        }
+    }
+}
+
+impl Renderer for RecordingRenderer {
+    fn set_fill_style(&mut self, color: &str) {
+        self.commands.push(DrawCommand::SetFillStyle(color.to_string()));
+    }
+
+    fn set_stroke_style(&mut self, color: &str) {
+        self.commands.push(DrawCommand::SetStrokeStyle(color.to_string()));
+    }
+
+    fn set_line_width(&mut self, width: f64) {
+        self.commands.push(DrawCommand::SetLineWidth(width));
+    }
+
+    fn begin_path(&mut self) {
+        self.commands.push(DrawCommand::BeginPath);
+    }
+
+    fn move_to(&mut self, x: f64, y: f64) {
+        self.commands.push(DrawCommand::MoveTo(x, y));
+    }
+
+    fn line_to(&mut self, x: f64, y: f64) {
+        self.commands.push(DrawCommand::LineTo(x, y));
+    }
+
+    fn bezier_curve_to(&mut self, cp1x: f64, cp1y: f64, cp2x: f64, cp2y: f64, x: f64, y: f64) {
+        self.commands.push(DrawCommand::BezierCurveTo(cp1x, cp1y, cp2x, cp2y, x, y));
+    }
+
+    fn arc(&mut self, x: f64, y: f64, radius: f64, start_angle: f64, end_angle: f64) {
+        self.commands.push(DrawCommand::Arc(x, y, radius, start_angle, end_angle));
+    }
+
+    fn close_path(&mut self) {
+        self.commands.push(DrawCommand::ClosePath);
+    }
+
+    fn fill(&mut self) {
+        self.commands.push(DrawCommand::Fill);
+    }
+
+    fn stroke(&mut self) {
+        self.commands.push(DrawCommand::Stroke);
+    }
+
+    fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        self.commands.push(DrawCommand::FillRect(x, y, width, height));
+    }
+}
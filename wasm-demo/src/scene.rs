@@ -0,0 +1,729 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use wasm_bindgen::prelude::*;
+use crate::camera::Camera;
+use crate::lsystem::{Bearing2D, Drawing2D, Drawing3D, Line2D, LineInfo, Marker2D, MarkerKind, Polygon, Position2D};
+use crate::render::Renderer;
+use crate::shapes::{FlowerSpec, LeafSpec};
+
+
+/// How a plant is scaled into the space above its pot, or into the space
+/// above the floor when it has none.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FitMode {
+    /// as large as possible with the whole plant inside the margins
+    Contain,
+    /// as large as possible while filling the space both across and up, so
+    /// the plant may overflow one of them
+    Cover,
+    /// scaled by `Style::scale` whatever the size of the canvas
+    Fixed,
+}
+
+/// The axis-aligned box around some geometry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: Position2D,
+    pub max: Position2D,
+}
+
+impl Bounds {
+    /// the box around every line, marker and polygon of a drawing, or `None`
+    /// if there are none
+    pub fn of(plant: &Drawing2D) -> Option<Bounds> {
+        let mut points = plant.lines.iter().flat_map(|l| [l.start, l.end])
+            .chain(plant.markers.iter().map(|m| m.position))
+            .chain(plant.polygons.iter().flat_map(|p| p.points.iter().copied()));
+        let first = points.next()?;
+        Some(points.fold(Bounds { min: first, max: first }, |b, p| Bounds {
+            min: Position2D { x: b.min.x.min(p.x), y: b.min.y.min(p.y) },
+            max: Position2D { x: b.max.x.max(p.x), y: b.max.y.max(p.y) },
+        }))
+    }
+
+    /// the width and height of the box
+    fn size(&self) -> (f64, f64) {
+        (self.max.x - self.min.x, self.max.y - self.min.y)
+    }
+
+    /// how far the box reaches to either side of the origin, above it and
+    /// below it
+    fn around_root(&self) -> (f64, f64, f64) {
+        (self.min.x.abs().max(self.max.x.abs()), (-self.min.y).max(0.0), self.max.y.max(0.0))
+    }
+}
+
+/// A uniform scale followed by a translation, taking plant coordinates to
+/// the canvas.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub scale: f64,
+    pub offset: Position2D,
+}
+
+impl Transform {
+    pub fn apply(&self, p: Position2D) -> Position2D {
+        Position2D {
+            x: p.x * self.scale + self.offset.x,
+            y: p.y * self.scale + self.offset.y,
+        }
+    }
+
+    pub fn apply_line(&self, line: &Line2D) -> Line2D {
+        Line2D { start: self.apply(line.start), end: self.apply(line.end) }
+    }
+
+    pub fn apply_marker(&self, marker: &Marker2D) -> Marker2D {
+        Marker2D { position: self.apply(marker.position), ..*marker }
+    }
+
+    pub fn apply_polygon(&self, polygon: &Polygon<Position2D>) -> Polygon<Position2D> {
+        Polygon { points: polygon.points.iter().map(|p| self.apply(*p)).collect(), ..*polygon }
+    }
+}
+
+/// The colours, sizes and layout of a plant scene.  The default is the look
+/// of the plants on the site.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Style {
+    pub sky_color: String,
+    pub floor_color: String,
+    pub pot_color: String,
+    pub stem_color: String,
+    pub petal_color: String,
+    pub pollen_color: String,
+    pub fruit_color: String,
+    /// the colours picked by `'` in a grammar, starting from the first.
+    /// Lines past the end use the last colour, and with no palette every
+    /// line is drawn in `stem_color`.
+    pub palette: Vec<String>,
+    /// width of the stems
+    pub line_width: f64,
+    pub flower_radius: f64,
+    /// thickness of the floor along the bottom of the scene
+    pub floor_height: f64,
+    /// height of the pot from the floor to the top of its rim, where the
+    /// plant is rooted
+    pub pot_height: f64,
+    pub pot_rim_width: f64,
+    pub pot_rim_height: f64,
+    /// width of the body of the pot just under the rim
+    pub pot_top_width: f64,
+    /// width of the body of the pot where it stands on the floor
+    pub pot_bottom_width: f64,
+    /// whether the plant is rooted in a pot.  Without one, the whole
+    /// drawing is centred above the floor instead, which suits curves and
+    /// tilings that don't grow from a root.
+    pub potted: bool,
+    pub fit: FitMode,
+    /// the scale used by `FitMode::Fixed`
+    pub scale: f64,
+    /// space kept clear between a fitted plant and the edges of the canvas
+    pub margin: f64,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            sky_color: "#d7fcff".to_string(),
+            floor_color: "#4A3728".to_string(),
+            pot_color: "#B35642".to_string(),
+            stem_color: "#4F7942".to_string(),
+            petal_color: "#C8A2C8".to_string(),
+            pollen_color: "#FDDA0D".to_string(),
+            fruit_color: "#B3202A".to_string(),
+            palette: vec![],
+            line_width: 3.0,
+            flower_radius: 20.0,
+            floor_height: 5.0,
+            pot_height: 45.0,
+            pot_rim_width: 60.0,
+            pot_rim_height: 10.0,
+            pot_top_width: 50.0,
+            pot_bottom_width: 30.0,
+            potted: true,
+            fit: FitMode::Fixed,
+            scale: 1.0,
+            margin: 25.0,
+        }
+    }
+}
+
+impl Style {
+    /// where the root of a plant goes on a canvas of the given size: centred,
+    /// on top of the pot
+    pub fn plant_origin(&self, width: f64, height: f64) -> (f64, f64) {
+        (width / 2.0, height - self.floor_height - self.pot_height)
+    }
+
+    /// the smallest image, at least 200 pixels square, that fits a plant
+    /// rooted at the origin and drawn at `scale` above the pot, with a
+    /// margin.  Anything hanging below the root needs room above the floor
+    /// too.
+    pub fn plant_size(&self, plant: &Drawing2D) -> (f64, f64) {
+        if !self.potted {
+            return self.figure_size(plant);
+        }
+        let (reach, rise, drop) = Bounds::of(plant).map_or((0.0, 0.0, 0.0), |b| b.around_root());
+        let width = (2.0 * (reach * self.scale + self.margin)).max(200.0).ceil();
+        let below = (drop * self.scale).max(self.pot_height);
+        let height = (rise * self.scale + self.margin + below + self.floor_height).max(200.0).ceil();
+        (width, height)
+    }
+
+    /// Place a plant rooted at the origin on a canvas of the given size.
+    /// The root always sits on the pot, and the plant is scaled by the fit
+    /// mode into the space above the floor and inside the margins.  A plant
+    /// hanging further below its root than the pot is tall lifts the pot
+    /// off the floor, so the lowest part of the plant rests on it instead.
+    pub fn plant_transform(&self, plant: &Drawing2D, width: f64, height: f64) -> Transform {
+        if !self.potted {
+            return self.figure_transform(plant, width, height);
+        }
+        let (x, y) = self.plant_origin(width, height);
+        let floor = height - self.floor_height;
+        let bounds = match (self.fit, Bounds::of(plant)) {
+            (FitMode::Fixed, bounds) | (_, bounds @ None) => {
+                let drop = bounds.map_or(0.0, |b| b.around_root().2);
+                let offset = Position2D { x, y: y.min(floor - drop * self.scale) };
+                return Transform { scale: self.scale, offset };
+            }
+            (_, Some(bounds)) => bounds,
+        };
+
+        // with the root centred, the plant can reach as far as the nearer
+        // side, as high as the top margin while the root stays on the pot,
+        // and fill everything down to the floor if it hangs below the pot
+        let (reach, rise, drop) = bounds.around_root();
+        let across = (x - self.margin) / reach;
+        let up = ((y - self.margin) / rise).min((floor - self.margin) / (rise + drop));
+        let candidates = [across, up];
+        let scales = candidates.iter().copied().filter(|s| s.is_finite() && *s > 0.0);
+        let scale = match self.fit {
+            FitMode::Contain => scales.reduce(f64::min),
+            _ => scales.reduce(f64::max),
+        }.unwrap_or(self.scale);
+        Transform { scale, offset: Position2D { x, y: y.min(floor - drop * scale) } }
+    }
+
+    /// like `plant_size`, for a drawing centred without a pot
+    fn figure_size(&self, plant: &Drawing2D) -> (f64, f64) {
+        let (across, up) = Bounds::of(plant).map_or((0.0, 0.0), |b| b.size());
+        let width = (across * self.scale + 2.0 * self.margin).max(200.0).ceil();
+        let height = (up * self.scale + 2.0 * self.margin + self.floor_height).max(200.0).ceil();
+        (width, height)
+    }
+
+    /// Centre a drawing in the space above the floor, scaled by the fit
+    /// mode to the whole of its bounds inside the margins
+    fn figure_transform(&self, plant: &Drawing2D, width: f64, height: f64) -> Transform {
+        let centre = Position2D { x: width / 2.0, y: (height - self.floor_height) / 2.0 };
+        let bounds = match Bounds::of(plant) {
+            Some(bounds) => bounds,
+            None => return Transform { scale: self.scale, offset: centre },
+        };
+        let (across, up) = bounds.size();
+        let candidates = [(width - 2.0 * self.margin) / across, (height - self.floor_height - 2.0 * self.margin) / up];
+        let scales = candidates.iter().copied().filter(|s| s.is_finite() && *s > 0.0);
+        let scale = match self.fit {
+            FitMode::Fixed => None,
+            FitMode::Contain => scales.reduce(f64::min),
+            FitMode::Cover => scales.reduce(f64::max),
+        }.unwrap_or(self.scale);
+        let middle = Position2D { x: (bounds.min.x + bounds.max.x) / 2.0, y: (bounds.min.y + bounds.max.y) / 2.0 };
+        Transform { scale, offset: Position2D { x: centre.x - middle.x * scale, y: centre.y - middle.y * scale } }
+    }
+}
+
+/// draw the sky and the floor
+pub fn draw_background(r: &mut dyn Renderer, style: &Style, width: f64, height: f64) {
+    r.set_fill_style(&style.sky_color);
+    r.fill_rect(0.0, 0.0, width, height);
+    r.set_fill_style(&style.floor_color);
+    r.fill_rect(0.0, height - style.floor_height, width, style.floor_height);
+}
+
+/// draw the pot with the top of its rim centred on (x, y)
+pub fn draw_pot(r: &mut dyn Renderer, style: &Style, x: f64, y: f64) {
+    let rim = y + style.pot_rim_height;
+    let floor = y + style.pot_height;
+    r.set_stroke_style(&style.pot_color);
+    r.set_fill_style(&style.pot_color);
+    r.fill_rect(x - style.pot_rim_width / 2.0, y, style.pot_rim_width, style.pot_rim_height);
+    r.begin_path();
+    r.move_to(x - style.pot_top_width / 2.0, rim);
+    r.line_to(x + style.pot_top_width / 2.0, rim);
+    r.line_to(x + style.pot_bottom_width / 2.0, floor);
+    r.line_to(x - style.pot_bottom_width / 2.0, floor);
+    r.close_path();
+    r.fill();
+    r.stroke();
+}
+
+/// Paints one kind of marker.  The marker is given in canvas coordinates,
+/// and `radius` is how far it may reach from its position.
+pub trait MarkerPainter {
+    fn paint(&self, r: &mut dyn Renderer, style: &Style, marker: &Marker2D, radius: f64);
+}
+
+impl<F> MarkerPainter for F where F: Fn(&mut dyn Renderer, &Style, &Marker2D, f64) {
+    fn paint(&self, r: &mut dyn Renderer, style: &Style, marker: &Marker2D, radius: f64) {
+        self(r, style, marker, radius)
+    }
+}
+
+/// The painter for each kind of marker.  The default paints flowers,
+/// leaves, buds and fruit, and markers of a kind without a painter are
+/// skipped.
+pub struct MarkerPainters {
+    painters: HashMap<MarkerKind, Box<dyn MarkerPainter>>,
+}
+
+impl Default for MarkerPainters {
+    fn default() -> Self {
+        let mut painters = MarkerPainters::empty();
+        painters.set(MarkerKind::Flower, FlowerSpec::default());
+        painters.set(MarkerKind::Leaf, LeafSpec::default());
+        painters.set(MarkerKind::Bud, paint_bud);
+        painters.set(MarkerKind::Fruit, paint_fruit);
+        painters
+    }
+}
+
+impl MarkerPainters {
+    pub fn empty() -> MarkerPainters {
+        MarkerPainters { painters: HashMap::new() }
+    }
+
+    /// paint markers of `kind` with `painter`, replacing any painter before
+    pub fn set(&mut self, kind: MarkerKind, painter: impl MarkerPainter + 'static) {
+        self.painters.insert(kind, Box::new(painter));
+    }
+
+    pub fn paint(&self, r: &mut dyn Renderer, style: &Style, marker: &Marker2D, radius: f64) {
+        if let Some(painter) = self.painters.get(&marker.kind) {
+            painter.paint(r, style, marker, radius);
+        }
+    }
+}
+
+/// the colour of a marker: the palette entry picked by `'`, exactly as for
+/// lines, or `own` if no `'` came before it or there is no palette
+pub fn marker_color<'a>(style: &'a Style, marker: &Marker2D, own: &'a str) -> &'a str {
+    match marker.color {
+        Some(index) if !style.palette.is_empty() => palette_color(style, index),
+        _ => own,
+    }
+}
+
+/// a closed bud, a small petal-coloured teardrop pointing the way the
+/// marker faces
+pub fn paint_bud(r: &mut dyn Renderer, style: &Style, marker: &Marker2D, radius: f64) {
+    let Position2D { x, y } = marker.position;
+    let size = radius * 0.3;
+    let rotation = marker.bearing.rotation;
+    let (sin, cos) = rotation.sin_cos();
+    r.set_fill_style(marker_color(style, marker, &style.petal_color));
+    r.begin_path();
+    r.move_to(x + 2.0 * size * cos, y + 2.0 * size * sin);
+    r.arc(x, y, size, rotation + PI / 3.0, rotation + PI * 5.0 / 3.0);
+    r.close_path();
+    r.fill();
+}
+
+/// a round berry hanging below the marker
+pub fn paint_fruit(r: &mut dyn Renderer, style: &Style, marker: &Marker2D, radius: f64) {
+    let size = radius * 0.3;
+    let Position2D { x, y } = marker.position;
+    r.set_fill_style(marker_color(style, marker, &style.fruit_color));
+    r.begin_path();
+    r.arc(x, y + size, size, 0.0, 2.0 * PI);
+    r.close_path();
+    r.fill();
+}
+
+/// add a line to the current path
+pub fn trace_line(r: &mut dyn Renderer, line: &Line2D) {
+    r.move_to(line.start.x, line.start.y);
+    r.line_to(line.end.x, line.end.y);
+}
+
+/// the width to stroke line `i` with: the style's line width scaled by the
+/// line's own relative width, if it has one
+pub fn line_width(style: &Style, info: &[LineInfo], i: usize) -> f64 {
+    style.line_width * info.get(i).map_or(1.0, |info| info.width)
+}
+
+/// the palette entry for a colour index, or the stem colour
+pub fn palette_color(style: &Style, index: usize) -> &str {
+    match style.palette.get(index).or_else(|| style.palette.last()) {
+        Some(color) => color,
+        None => &style.stem_color,
+    }
+}
+
+/// the colour to stroke line `i` with: the palette entry picked by the
+/// line's colour index, or the stem colour
+pub fn line_color<'a>(style: &'a Style, info: &[LineInfo], i: usize) -> &'a str {
+    palette_color(style, info.get(i).map_or(0, |info| info.color))
+}
+
+/// fill a polygon, given in canvas coordinates, in its palette colour
+pub fn draw_polygon(r: &mut dyn Renderer, style: &Style, polygon: &Polygon<Position2D>) {
+    let (first, rest) = match polygon.points.split_first() {
+        Some(points) => points,
+        None => return,
+    };
+    r.set_fill_style(palette_color(style, polygon.color));
+    r.begin_path();
+    r.move_to(first.x, first.y);
+    for p in rest {
+        r.line_to(p.x, p.y);
+    }
+    r.close_path();
+    r.fill();
+}
+
+/// Parse a palette written as colours separated by spaces or commas.  Hex
+/// colours may leave out the `#`, which starts a comment in a grammar.
+pub fn parse_palette(text: &str) -> Vec<String> {
+    text.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|color| !color.is_empty())
+        .map(|color| {
+            let bare_hex = [3, 4, 6, 8].contains(&color.len()) && color.chars().all(|c| c.is_ascii_hexdigit());
+            if bare_hex { format!("#{}", color) } else { color.to_string() }
+        })
+        .collect()
+}
+
+/// Strokes lines in as few paths as possible, starting a new path whenever
+/// the width or colour changes.
+#[derive(Default)]
+pub struct LineBatch {
+    stroke: Option<(f64, String)>,
+}
+
+impl LineBatch {
+    pub fn new() -> LineBatch {
+        LineBatch { stroke: None }
+    }
+
+    pub fn add(&mut self, r: &mut dyn Renderer, line: &Line2D, width: f64, color: &str) {
+        if !matches!(&self.stroke, Some((w, c)) if *w == width && c == color) {
+            if self.stroke.is_some() {
+                r.stroke();
+            }
+            r.set_stroke_style(color);
+            r.set_line_width(width);
+            r.begin_path();
+            self.stroke = Some((width, color.to_string()));
+        }
+        trace_line(r, line);
+    }
+
+    /// stroke whatever has been added so far, starting a new path for the
+    /// lines that follow
+    pub fn flush(&mut self, r: &mut dyn Renderer) {
+        if self.stroke.take().is_some() {
+            r.stroke();
+        }
+    }
+
+    /// stroke whatever has been added since the last stroke
+    pub fn finish(self, r: &mut dyn Renderer) {
+        if self.stroke.is_some() {
+            r.stroke();
+        }
+    }
+}
+
+/// draw a whole plant scene at once: background, pot, stems, polygons and
+/// markers, with the plant placed by `Style::plant_transform` and markers
+/// painted by the default painters
+pub fn draw_plant(r: &mut dyn Renderer, style: &Style, plant: &Drawing2D, width: f64, height: f64) {
+    draw_plant_with(r, style, &MarkerPainters::default(), plant, width, height);
+}
+
+/// like `draw_plant`, but paint markers with `painters`
+pub fn draw_plant_with(
+    r: &mut dyn Renderer,
+    style: &Style,
+    painters: &MarkerPainters,
+    plant: &Drawing2D,
+    width: f64,
+    height: f64,
+) {
+    let transform = style.plant_transform(plant, width, height);
+
+    draw_background(r, style, width, height);
+    if style.potted {
+        draw_pot(r, style, transform.offset.x, transform.offset.y);
+    }
+
+    let mut batch = LineBatch::new();
+    for (i, line) in plant.lines.iter().enumerate() {
+        batch.add(r, &transform.apply_line(line), line_width(style, &plant.info, i), line_color(style, &plant.info, i));
+    }
+    batch.finish(r);
+
+    for polygon in plant.polygons.iter() {
+        draw_polygon(r, style, &transform.apply_polygon(polygon));
+    }
+    for marker in plant.markers.iter() {
+        painters.paint(r, style, &transform.apply_marker(marker), style.flower_radius * marker.scale);
+    }
+}
+
+/// A projected part of a 3D plant, waiting to be drawn in depth order.
+enum Shape {
+    /// a line and its index in the drawing
+    Line(Line2D, usize),
+    Polygon(Polygon<Position2D>),
+    Marker(Marker2D),
+}
+
+/// draw a 3D plant seen through `camera` over the background, in one pass
+/// from the farthest to the nearest so near branches, polygons and markers
+/// cover far ones
+pub fn draw_plant_3d(
+    r: &mut dyn Renderer,
+    style: &Style,
+    painters: &MarkerPainters,
+    plant: &Drawing3D,
+    camera: &Camera,
+    width: f64,
+    height: f64,
+) {
+    draw_background(r, style, width, height);
+
+    let mut shapes: Vec<(f64, Shape)> = camera.project_lines(&plant.lines, width, height)
+        .into_iter()
+        .map(|(i, line, depth)| (depth, Shape::Line(line, i)))
+        .collect();
+    for polygon in plant.polygons.iter() {
+        let projected: Option<Vec<_>> = polygon.points.iter().map(|p| camera.project(p, width, height)).collect();
+        if let Some(projected) = projected {
+            let depth = projected.iter().map(|p| p.depth).sum::<f64>() / projected.len() as f64;
+            let points = projected.iter().map(|p| p.position).collect();
+            shapes.push((depth, Shape::Polygon(Polygon { points, color: polygon.color, parent: polygon.parent })));
+        }
+    }
+    for marker in plant.markers.iter() {
+        if let Some(p) = camera.project(&marker.position, width, height) {
+            // face the way the heading points on the canvas, or up if it
+            // points straight at the camera
+            let ahead = camera.project(&marker.position.add(&marker.bearing.heading), width, height)
+                .map(|a| (a.position.y - p.position.y, a.position.x - p.position.x))
+                .filter(|&(dy, dx)| dx != 0.0 || dy != 0.0);
+            let rotation = ahead.map_or(-PI / 2.0, |(dy, dx)| dy.atan2(dx));
+            let projected = Marker2D {
+                kind: marker.kind,
+                position: p.position,
+                bearing: Bearing2D { rotation },
+                scale: marker.scale,
+                color: marker.color,
+            };
+            shapes.push((p.depth, Shape::Marker(projected)));
+        }
+    }
+    // the sort is stable, so at the same depth markers still go on top
+    shapes.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    for (_, shape) in shapes {
+        match shape {
+            Shape::Line(line, i) => {
+                r.set_line_width(line_width(style, &plant.info, i));
+                r.set_stroke_style(line_color(style, &plant.info, i));
+                r.begin_path();
+                trace_line(r, &line);
+                r.stroke();
+            }
+            Shape::Polygon(polygon) => draw_polygon(r, style, &polygon),
+            Shape::Marker(marker) => painters.paint(r, style, &marker, 8.0 * marker.scale),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsystem::{Bearing3D, Drawing, Line3D, Marker, Position3D};
+    use crate::render::{DrawCommand, RecordingRenderer};
+
+    fn plant(lines: &[Line2D], flowers: &[Position2D]) -> Drawing2D {
+        let flower = |p| Marker::new(MarkerKind::Flower, p, Bearing2D { rotation: 0.0 });
+        Drawing { lines: lines.to_vec(), markers: flowers.iter().copied().map(flower).collect(), ..Drawing::default() }
+    }
+
+    #[test]
+    fn draws_plants_above_the_pot() {
+        let line = Line2D {
+            start: Position2D { x: 0.0, y: 0.0 },
+            end: Position2D { x: 0.0, y: -10.0 },
+        };
+        let mut r = RecordingRenderer::new();
+        draw_plant(&mut r, &Style::default(), &plant(&[line], &[line.end]), 200.0, 100.0);
+
+        let stem = r.commands.iter().position(|c| *c == DrawCommand::SetStrokeStyle(Style::default().stem_color)).unwrap();
+        assert_eq!(
+            r.commands[stem + 2..stem + 5],
+            [DrawCommand::BeginPath, DrawCommand::MoveTo(100.0, 50.0), DrawCommand::LineTo(100.0, 40.0)]
+        );
+        // one flower, with its pollen centred on the end of the stem
+        assert!(r.commands.contains(&DrawCommand::Arc(100.0, 40.0, 3.0, 0.0, 2.0 * PI)));
+        assert_eq!(r.commands.iter().filter(|c| **c == DrawCommand::Fill).count(), 3);
+    }
+
+    #[test]
+    fn colours_lines_from_the_palette() {
+        let info = |color| LineInfo { depth: 0, distance: 0.0, width: 1.0, parent: None, color, generation: 0 };
+        let info = [info(0), info(1), info(5)];
+        let style = Style { palette: parse_palette("4F7942, #8B5A2B orange"), ..Style::default() };
+        assert_eq!(style.palette, vec!["#4F7942", "#8B5A2B", "orange"]);
+        assert_eq!(line_color(&style, &info, 1), "#8B5A2B");
+        // past the end of the palette, and without one
+        assert_eq!(line_color(&style, &info, 2), "orange");
+        assert_eq!(line_color(&Style::default(), &info, 1), "#4F7942");
+
+        // a colour change starts a new path
+        let line = Line2D { start: Position2D { x: 0.0, y: 0.0 }, end: Position2D { x: 0.0, y: -10.0 } };
+        let mut r = RecordingRenderer::new();
+        let plant = Drawing { info: info.to_vec(), ..plant(&[line, line, line], &[]) };
+        draw_plant(&mut r, &style, &plant, 200.0, 100.0);
+        let strokes: Vec<_> = r.commands.iter().filter_map(|c| match c {
+            DrawCommand::SetStrokeStyle(color) => Some(color.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(strokes, vec![Style::default().pot_color.as_str(), "#4F7942", "#8B5A2B", "orange"]);
+    }
+
+    #[test]
+    fn colours_markers_like_lines() {
+        let flower = |color| Marker { color, ..Marker::new(MarkerKind::Flower, Position2D { x: 0.0, y: 0.0 }, Bearing2D { rotation: 0.0 }) };
+        let style = Style { palette: parse_palette("4F7942 8B5A2B"), ..Style::default() };
+        let own = style.petal_color.as_str();
+        // before any `'` a marker keeps its own colour, and after one it
+        // takes the same entry a line would, including the first
+        assert_eq!(marker_color(&style, &flower(None), own), own);
+        assert_eq!(marker_color(&style, &flower(Some(0)), own), "#4F7942");
+        assert_eq!(marker_color(&style, &flower(Some(1)), own), "#8B5A2B");
+        assert_eq!(marker_color(&Style::default(), &flower(Some(1)), own), own);
+    }
+
+    #[test]
+    fn fills_polygons_in_their_colour() {
+        let corner = |x, y| Position2D { x, y };
+        let leaf = Polygon { points: vec![corner(0.0, 0.0), corner(10.0, -10.0), corner(0.0, -20.0)], color: 1, parent: None };
+        let style = Style { palette: parse_palette("4F7942 9ACD32"), ..Style::default() };
+        let mut r = RecordingRenderer::new();
+        draw_plant(&mut r, &style, &Drawing { polygons: vec![leaf], ..Drawing::default() }, 200.0, 100.0);
+
+        let fill = r.commands.iter().position(|c| *c == DrawCommand::SetFillStyle("#9ACD32".to_string())).unwrap();
+        assert_eq!(
+            r.commands[fill + 1..fill + 7],
+            [
+                DrawCommand::BeginPath,
+                DrawCommand::MoveTo(100.0, 50.0),
+                DrawCommand::LineTo(110.0, 40.0),
+                DrawCommand::LineTo(100.0, 30.0),
+                DrawCommand::ClosePath,
+                DrawCommand::Fill,
+            ]
+        );
+    }
+
+    #[test]
+    fn paints_markers_by_kind() {
+        let at = |kind, x| Marker { kind, position: Position2D { x, y: -10.0 }, bearing: Bearing2D { rotation: 0.0 }, scale: 0.5, color: None };
+        let markers = vec![at(MarkerKind::Fruit, 0.0), at(MarkerKind::Custom(7), 10.0), at(MarkerKind::Custom(8), 20.0)];
+        let plant = Drawing { markers, ..Drawing::default() };
+        let mut painters = MarkerPainters::default();
+        painters.set(MarkerKind::Custom(7), |r: &mut dyn Renderer, _: &Style, m: &Marker2D, radius: f64| {
+            r.fill_rect(m.position.x, m.position.y, radius, radius);
+        });
+        let mut r = RecordingRenderer::new();
+        draw_plant_with(&mut r, &Style::default(), &painters, &plant, 200.0, 100.0);
+
+        // a berry half the usual size, the custom marker, and nothing for
+        // the kind without a painter
+        assert!(r.commands.contains(&DrawCommand::SetFillStyle(Style::default().fruit_color)));
+        assert!(r.commands.contains(&DrawCommand::Arc(100.0, 43.0, 3.0, 0.0, 2.0 * PI)));
+        assert_eq!(r.commands.last(), Some(&DrawCommand::FillRect(110.0, 40.0, 10.0, 10.0)));
+    }
+
+    #[test]
+    fn fits_plants_above_the_pot() {
+        let tall = Line2D {
+            start: Position2D { x: 0.0, y: 0.0 },
+            end: Position2D { x: 10.0, y: -1000.0 },
+        };
+        // the default keeps plants at their own size, rooted on the pot
+        let fixed = Style::default().plant_transform(&plant(&[tall], &[]), 200.0, 300.0);
+        assert_eq!(fixed, Transform { scale: 1.0, offset: Position2D { x: 100.0, y: 250.0 } });
+
+        // contained, the top of the plant reaches the top margin
+        let style = Style { fit: FitMode::Contain, ..Style::default() };
+        let contain = style.plant_transform(&plant(&[tall], &[]), 200.0, 300.0);
+        assert!((contain.apply(tall.end).y - 25.0).abs() < 1e-9);
+
+        // covering, a wide bush fills the width and may overflow upwards
+        let wide = Line2D { start: tall.start, end: Position2D { x: -150.0, y: -100.0 } };
+        let style = Style { fit: FitMode::Cover, ..Style::default() };
+        let cover = style.plant_transform(&plant(&[wide], &[]), 200.0, 300.0);
+        assert_eq!(cover.scale, 2.25);
+        assert_eq!(style.plant_size(&Drawing::default()), (200.0, 200.0));
+    }
+
+    #[test]
+    fn fits_plants_hanging_below_the_root() {
+        let stem = Line2D { start: Position2D { x: 0.0, y: 0.0 }, end: Position2D { x: 0.0, y: -100.0 } };
+        let root = Line2D { start: stem.start, end: Position2D { x: 20.0, y: 100.0 } };
+        let hanging = plant(&[stem, root], &[]);
+
+        // contained, the plant spans from the top margin down to the floor,
+        // lifting the pot with it
+        let style = Style { fit: FitMode::Contain, ..Style::default() };
+        let contain = style.plant_transform(&hanging, 200.0, 300.0);
+        assert!((contain.apply(stem.end).y - 25.0).abs() < 1e-9);
+        assert!((contain.apply(root.end).y - 295.0).abs() < 1e-9);
+
+        // at a fixed scale the image is tall enough for both ends
+        let style = Style::default();
+        let (width, height) = style.plant_size(&hanging);
+        assert_eq!((width, height), (200.0, 230.0));
+        let fixed = style.plant_transform(&hanging, width, height);
+        assert_eq!(fixed.apply(stem.end).y, 25.0);
+        assert_eq!(fixed.apply(root.end).y, height - style.floor_height);
+    }
+
+    #[test]
+    fn draws_far_flowers_under_near_lines() {
+        let camera = Camera::orthographic(Position3D::new(0.0, 0.0, 10.0), Position3D::new(0.0, 0.0, 0.0), 10.0);
+        let line = Line3D { start: Position3D::new(0.0, 0.0, 1.0), end: Position3D::new(1.0, 0.0, 1.0) };
+        let mut r = RecordingRenderer::new();
+        let flower = Marker::new(MarkerKind::Flower, Position3D::new(0.0, 0.0, -1.0), Bearing3D::identity());
+        let plant = Drawing { lines: vec![line], markers: vec![flower], ..Drawing::default() };
+        draw_plant_3d(&mut r, &Style::default(), &MarkerPainters::default(), &plant, &camera, 100.0, 100.0);
+
+        let flower = r.commands.iter().position(|c| matches!(c, DrawCommand::Arc(..))).unwrap();
+        let line = r.commands.iter().position(|c| *c == DrawCommand::LineTo(60.0, 50.0)).unwrap();
+        assert!(flower < line);
+    }
+
+    #[test]
+    fn draws_3d_lines_at_their_widths() {
+        let camera = Camera::orthographic(Position3D::new(0.0, 0.0, 10.0), Position3D::new(0.0, 0.0, 0.0), 10.0);
+        let line = Line3D { start: Position3D::new(0.0, 0.0, 0.0), end: Position3D::new(1.0, 0.0, 0.0) };
+        let info = |width| LineInfo { depth: 0, distance: 0.0, width, parent: None, color: 0, generation: 0 };
+        let plant = Drawing { lines: vec![line, line], info: vec![info(1.0), info(0.5)], ..Drawing::default() };
+        let mut r = RecordingRenderer::new();
+        draw_plant_3d(&mut r, &Style::default(), &MarkerPainters::default(), &plant, &camera, 100.0, 100.0);
+        let widths: Vec<f64> = r.commands.iter().filter_map(|c| match c {
+            DrawCommand::SetLineWidth(w) => Some(*w),
+            _ => None,
+        }).collect();
+        assert_eq!(widths, vec![3.0, 1.5]);
+    }
+}
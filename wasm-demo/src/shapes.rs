@@ -0,0 +1,316 @@
+use std::f64::consts::PI;
+use crate::lsystem::{Marker2D, Position2D};
+use crate::render::Renderer;
+use crate::scene::{self, MarkerPainter, Style};
+
+
+/// One step of a `Path`, in the terms of the Canvas2D path API.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathCommand {
+    MoveTo(Position2D),
+    LineTo(Position2D),
+    /// a cubic bezier curve through two control points to its end
+    CurveTo(Position2D, Position2D, Position2D),
+    /// a clockwise arc around a centre between two angles in radians
+    Arc { center: Position2D, radius: f64, start: f64, end: f64 },
+    Close,
+}
+
+/// Path geometry built once and traced onto any `Renderer`, so the same
+/// shape comes out alike on a canvas and in an SVG document.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Path {
+    pub commands: Vec<PathCommand>,
+}
+
+impl Path {
+    pub fn new() -> Path {
+        Path { commands: vec![] }
+    }
+
+    pub fn move_to(&mut self, p: Position2D) {
+        self.commands.push(PathCommand::MoveTo(p));
+    }
+
+    pub fn line_to(&mut self, p: Position2D) {
+        self.commands.push(PathCommand::LineTo(p));
+    }
+
+    pub fn curve_to(&mut self, cp1: Position2D, cp2: Position2D, p: Position2D) {
+        self.commands.push(PathCommand::CurveTo(cp1, cp2, p));
+    }
+
+    pub fn arc(&mut self, center: Position2D, radius: f64, start: f64, end: f64) {
+        self.commands.push(PathCommand::Arc { center, radius, start, end });
+    }
+
+    pub fn close(&mut self) {
+        self.commands.push(PathCommand::Close);
+    }
+
+    /// add the path to the renderer's current path
+    pub fn trace(&self, r: &mut dyn Renderer) {
+        for command in self.commands.iter() {
+            match *command {
+                PathCommand::MoveTo(p) => r.move_to(p.x, p.y),
+                PathCommand::LineTo(p) => r.line_to(p.x, p.y),
+                PathCommand::CurveTo(a, b, p) => r.bezier_curve_to(a.x, a.y, b.x, b.y, p.x, p.y),
+                PathCommand::Arc { center, radius, start, end } => r.arc(center.x, center.y, radius, start, end),
+                PathCommand::Close => r.close_path(),
+            }
+        }
+    }
+}
+
+/// A path and the colour to fill it with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilledPath {
+    pub path: Path,
+    pub color: String,
+}
+
+/// fill each path in turn, so later paths cover earlier ones
+pub fn fill_paths(r: &mut dyn Renderer, paths: &[FilledPath]) {
+    for filled in paths {
+        r.set_fill_style(&filled.color);
+        r.begin_path();
+        filled.path.trace(r);
+        r.fill();
+    }
+}
+
+/// Parse a `#RGB` or `#RRGGBB` colour into its red, green and blue.
+fn parse_hex_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |s: &str| u8::from_str_radix(s, 16).ok();
+    match hex.len() {
+        3 => {
+            let mut rgb = [0; 3];
+            for (i, c) in hex.chars().enumerate() {
+                rgb[i] = channel(&c.to_string())? * 17;
+            }
+            Some(rgb)
+        }
+        6 => Some([channel(&hex[0..2])?, channel(&hex[2..4])?, channel(&hex[4..6])?]),
+        _ => None,
+    }
+}
+
+/// The colour `t` of the way from `from` to `to`.  Colours other than hex
+/// ones can't be mixed, so the nearer of the two is used instead.
+pub fn mix_colors(from: &str, to: &str, t: f64) -> String {
+    match (parse_hex_color(from), parse_hex_color(to)) {
+        _ if t <= 0.0 => from.to_string(),
+        _ if t >= 1.0 => to.to_string(),
+        (Some(a), Some(b)) => {
+            let mix = |i: usize| (f64::from(a[i]) + (f64::from(b[i]) - f64::from(a[i])) * t).round() as u8;
+            format!("#{:02X}{:02X}{:02X}", mix(0), mix(1), mix(2))
+        }
+        _ if t < 0.5 => from.to_string(),
+        _ => to.to_string(),
+    }
+}
+
+/// the point `distance` from `center` at `angle`, measured the way the
+/// site's flowers always have been: from straight down, towards the right
+fn polar(center: Position2D, distance: f64, angle: f64) -> Position2D {
+    Position2D { x: center.x + distance * angle.sin(), y: center.y + distance * angle.cos() }
+}
+
+
+/// The shape of a flower facing the viewer: whorls of curved petals around
+/// a round centre.  The default is the site's five-petalled flower.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlowerSpec {
+    /// petals in each whorl
+    pub petals: u32,
+    /// how wide each petal curves, as a fraction of its share of the whorl
+    pub petal_width: f64,
+    /// how far the outer petals reach, as a fraction of the marker radius
+    pub radius: f64,
+    /// turns the whole flower by this many radians
+    pub rotation: f64,
+    /// rings of petals, each inside the last and turned by half a petal so
+    /// its petals show between those behind
+    pub whorls: u32,
+    /// how much smaller each whorl is than the one around it
+    pub whorl_scale: f64,
+    /// the colour of the innermost whorl, with the whorls between shading
+    /// from the petal colour to it.  `None` keeps every whorl the same.
+    pub inner_color: Option<String>,
+    /// the radius of the centre, as a fraction of the marker radius
+    pub center_radius: f64,
+}
+
+impl Default for FlowerSpec {
+    fn default() -> Self {
+        FlowerSpec {
+            petals: 5,
+            petal_width: 1.0,
+            radius: 1.0,
+            rotation: 0.0,
+            whorls: 1,
+            whorl_scale: 0.6,
+            inner_color: None,
+            center_radius: 0.15,
+        }
+    }
+}
+
+impl FlowerSpec {
+    /// the filled paths of a flower of `radius` around `center`, from the
+    /// outer whorl in to the centre
+    pub fn paths(&self, center: Position2D, radius: f64, petal_color: &str, center_color: &str) -> Vec<FilledPath> {
+        let mut paths = vec![];
+        if self.petals > 0 {
+            let slot = PI * 2.0 / f64::from(self.petals);
+            for whorl in 0..self.whorls {
+                let reach = radius * self.radius * self.whorl_scale.powi(whorl as i32);
+                let turn = self.rotation + slot / 2.0 * f64::from(whorl);
+                let mut path = Path::new();
+                for i in 0..self.petals {
+                    let middle = f64::from(i) + 0.5;
+                    path.move_to(center);
+                    path.curve_to(
+                        polar(center, reach, turn + slot * (middle + self.petal_width / 2.0)),
+                        polar(center, reach, turn + slot * (middle - self.petal_width / 2.0)),
+                        center,
+                    );
+                }
+                path.close();
+                let color = match &self.inner_color {
+                    Some(inner) if self.whorls > 1 => {
+                        mix_colors(petal_color, inner, f64::from(whorl) / f64::from(self.whorls - 1))
+                    }
+                    _ => petal_color.to_string(),
+                };
+                paths.push(FilledPath { path, color });
+            }
+        }
+        if self.center_radius > 0.0 {
+            let mut path = Path::new();
+            path.arc(center, radius * self.center_radius, 0.0, 2.0 * PI);
+            path.close();
+            paths.push(FilledPath { path, color: center_color.to_string() });
+        }
+        paths
+    }
+}
+
+/// Paints flowers in the marker's petal colour with pollen in the middle.
+impl MarkerPainter for FlowerSpec {
+    fn paint(&self, r: &mut dyn Renderer, style: &Style, marker: &Marker2D, radius: f64) {
+        let petal_color = scene::marker_color(style, marker, &style.petal_color);
+        fill_paths(r, &self.paths(marker.position, radius, petal_color, &style.pollen_color));
+    }
+}
+
+
+/// The shape of a leaf, a blade curving out from its base to a point.  The
+/// default is the site's leaf.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LeafSpec {
+    /// from the base to the tip, as a fraction of the marker radius
+    pub length: f64,
+    /// how far the blade bulges near its base, as a fraction of the radius
+    pub width: f64,
+    /// how far it bulges near its tip, as a fraction of the radius
+    pub tip_width: f64,
+    /// turns the leaf from the way the marker faces by this many radians
+    pub rotation: f64,
+}
+
+impl Default for LeafSpec {
+    fn default() -> Self {
+        LeafSpec { length: 1.0, width: 0.35, tip_width: 0.3, rotation: 0.0 }
+    }
+}
+
+impl LeafSpec {
+    /// the outline of a leaf of `radius` growing from `base` towards
+    /// `rotation`, in radians clockwise from the x axis
+    pub fn path(&self, base: Position2D, rotation: f64, radius: f64) -> Path {
+        let (sin, cos) = (rotation + self.rotation).sin_cos();
+        // along the leaf, and across it
+        let along = |d: f64, w: f64| Position2D {
+            x: base.x + radius * (d * cos - w * sin),
+            y: base.y + radius * (d * sin + w * cos),
+        };
+        let mut path = Path::new();
+        path.move_to(base);
+        path.curve_to(along(self.length * 0.3, self.width), along(self.length * 0.7, self.tip_width), along(self.length, 0.0));
+        path.curve_to(along(self.length * 0.7, -self.tip_width), along(self.length * 0.3, -self.width), base);
+        path.close();
+        path
+    }
+}
+
+/// Paints leaves pointing the way the marker faces, in the stem colour.
+impl MarkerPainter for LeafSpec {
+    fn paint(&self, r: &mut dyn Renderer, style: &Style, marker: &Marker2D, radius: f64) {
+        let path = self.path(marker.position, marker.bearing.rotation, radius);
+        let color = scene::marker_color(style, marker, &style.stem_color).to_string();
+        fill_paths(r, &[FilledPath { path, color }]);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsystem::{Bearing2D, MarkerKind};
+    use crate::render::{DrawCommand, RecordingRenderer};
+
+    #[test]
+    fn mixes_hex_colours() {
+        assert_eq!(mix_colors("#000000", "#FFF", 0.5), "#808080");
+        assert_eq!(mix_colors("#C8A2C8", "#FFFFFF", 0.0), "#C8A2C8");
+        assert_eq!(mix_colors("red", "#FFFFFF", 0.25), "red");
+        assert_eq!(mix_colors("red", "#FFFFFF", 0.75), "#FFFFFF");
+    }
+
+    #[test]
+    fn builds_whorls_of_petals() {
+        let origin = Position2D { x: 0.0, y: 0.0 };
+        let spec = FlowerSpec {
+            petals: 4,
+            whorls: 3,
+            whorl_scale: 0.5,
+            inner_color: Some("#FFFFFF".to_string()),
+            ..FlowerSpec::default()
+        };
+        let paths = spec.paths(origin, 10.0, "#000000", "#FDDA0D");
+        let colors: Vec<_> = paths.iter().map(|p| p.color.as_str()).collect();
+        assert_eq!(colors, vec!["#000000", "#808080", "#FFFFFF", "#FDDA0D"]);
+
+        // each whorl has a curve per petal, reaching half as far as the last
+        let reach = |path: &Path| path.commands.iter().filter_map(|c| match c {
+            PathCommand::CurveTo(a, _, _) => Some((a.x * a.x + a.y * a.y).sqrt()),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(reach(&paths[0].path).len(), 4);
+        assert!((reach(&paths[1].path)[0] - 5.0).abs() < 1e-9);
+        assert!((reach(&paths[2].path)[0] - 2.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn paints_the_default_flower_and_leaf() {
+        let marker = Marker2D::new(MarkerKind::Flower, Position2D { x: 10.0, y: 10.0 }, Bearing2D { rotation: 0.0 });
+        let mut r = RecordingRenderer::new();
+        FlowerSpec::default().paint(&mut r, &Style::default(), &marker, 20.0);
+        assert_eq!(r.commands[0], DrawCommand::SetFillStyle(Style::default().petal_color));
+        assert_eq!(r.commands.iter().filter(|c| matches!(c, DrawCommand::BezierCurveTo(..))).count(), 5);
+        assert!(r.commands.contains(&DrawCommand::Arc(10.0, 10.0, 3.0, 0.0, 2.0 * PI)));
+
+        // a leaf facing right reaches its tip one radius along
+        let path = LeafSpec::default().path(marker.position, 0.0, 20.0);
+        assert_eq!(path.commands[2], PathCommand::CurveTo(
+            Position2D { x: 24.0, y: 4.0 },
+            Position2D { x: 16.0, y: 3.0 },
+            marker.position,
+        ));
+    }
+}
@@ -0,0 +1,253 @@
+use std::f64::consts::PI;
+use std::fmt::Write;
+use crate::lsystem::Drawing2D;
+use crate::render::Renderer;
+use crate::scene::{self, MarkerPainters, Style};
+
+
+/// format a coordinate with at most two decimals and no trailing zeros, so
+/// documents stay small and diff cleanly
+fn num(v: f64) -> String {
+    let s = format!("{:.2}", v);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0".to_string() } else { s.to_string() }
+}
+
+/// escape text for an attribute value, so a colour given by the user can't
+/// end the attribute or start an element
+fn attr(v: &str) -> String {
+    v.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// A minimal SVG document builder.
+pub struct SvgDocument {
+    width: f64,
+    height: f64,
+    body: String,
+}
+
+impl SvgDocument {
+    pub fn new(width: f64, height: f64) -> SvgDocument {
+        SvgDocument { width, height, body: String::new() }
+    }
+
+    pub fn rect(&mut self, x: f64, y: f64, width: f64, height: f64, fill: &str) {
+        writeln!(
+            self.body,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+            num(x), num(y), num(width), num(height), attr(fill)
+        ).unwrap();
+    }
+
+    pub fn circle(&mut self, cx: f64, cy: f64, r: f64, fill: &str) {
+        writeln!(self.body, r#"<circle cx="{}" cy="{}" r="{}" fill="{}"/>"#, num(cx), num(cy), num(r), attr(fill)).unwrap();
+    }
+
+    /// a filled path from SVG path data
+    pub fn fill_path(&mut self, d: &str, fill: &str) {
+        writeln!(self.body, r#"<path d="{}" fill="{}"/>"#, attr(d), attr(fill)).unwrap();
+    }
+
+    /// a stroked path from SVG path data
+    pub fn stroke_path(&mut self, d: &str, stroke: &str, width: f64) {
+        writeln!(
+            self.body,
+            r#"<path d="{}" fill="none" stroke="{}" stroke-width="{}"/>"#,
+            attr(d), attr(stroke), num(width)
+        ).unwrap();
+    }
+
+    /// the finished document
+    pub fn finish(self) -> String {
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n{}</svg>\n",
+            self.body,
+            w = num(self.width),
+            h = num(self.height),
+        )
+    }
+}
+
+
+/// Renders onto an `SvgDocument`, turning each filled or stroked path into a
+/// `<path>` element.
+pub struct SvgRenderer {
+    document: SvgDocument,
+    fill: String,
+    stroke: String,
+    line_width: f64,
+    path: String,
+}
+
+impl SvgRenderer {
+    pub fn new(width: f64, height: f64) -> SvgRenderer {
+        SvgRenderer {
+            document: SvgDocument::new(width, height),
+            fill: "#000".to_string(),
+            stroke: "#000".to_string(),
+            line_width: 1.0,
+            path: String::new(),
+        }
+    }
+
+    /// the finished document
+    pub fn finish(self) -> String {
+        self.document.finish()
+    }
+}
+
+impl Renderer for SvgRenderer {
+    fn set_fill_style(&mut self, color: &str) {
+        self.fill = color.to_string();
+    }
+
+    fn set_stroke_style(&mut self, color: &str) {
+        self.stroke = color.to_string();
+    }
+
+    fn set_line_width(&mut self, width: f64) {
+        self.line_width = width;
+    }
+
+    fn begin_path(&mut self) {
+        self.path.clear();
+    }
+
+    fn move_to(&mut self, x: f64, y: f64) {
+        write!(self.path, "M{} {}", num(x), num(y)).unwrap();
+    }
+
+    fn line_to(&mut self, x: f64, y: f64) {
+        write!(self.path, "L{} {}", num(x), num(y)).unwrap();
+    }
+
+    fn bezier_curve_to(&mut self, cp1x: f64, cp1y: f64, cp2x: f64, cp2y: f64, x: f64, y: f64) {
+        write!(
+            self.path,
+            "C{} {} {} {} {} {}",
+            num(cp1x), num(cp1y), num(cp2x), num(cp2y), num(x), num(y)
+        ).unwrap();
+    }
+
+    fn arc(&mut self, x: f64, y: f64, radius: f64, start_angle: f64, end_angle: f64) {
+        let point = |angle: f64| (x + radius * angle.cos(), y + radius * angle.sin());
+        // like Canvas2D, the arc is joined to the current path by a line
+        let (sx, sy) = point(start_angle);
+        let join = if self.path.is_empty() { 'M' } else { 'L' };
+        write!(self.path, "{}{} {}", join, num(sx), num(sy)).unwrap();
+
+        let sweep = end_angle - start_angle;
+        // an SVG arc cannot start and end on the same point, so full circles
+        // are drawn as two halves
+        let ends = if sweep >= 2.0 * PI {
+            vec![(start_angle + PI, true), (start_angle, true)]
+        } else {
+            let sweep = sweep.rem_euclid(2.0 * PI);
+            vec![(start_angle + sweep, sweep > PI)]
+        };
+        for (angle, large) in ends {
+            let (ex, ey) = point(angle);
+            write!(
+                self.path,
+                "A{r} {r} 0 {} 1 {} {}",
+                u8::from(large), num(ex), num(ey),
+                r = num(radius),
+            ).unwrap();
+        }
+    }
+
+    fn close_path(&mut self) {
+        if !self.path.is_empty() {
+            self.path.push('Z');
+        }
+    }
+
+    fn fill(&mut self) {
+        if !self.path.is_empty() {
+            self.document.fill_path(&self.path, &self.fill);
+        }
+    }
+
+    fn stroke(&mut self) {
+        if !self.path.is_empty() {
+            self.document.stroke_path(&self.path, &self.stroke, self.line_width);
+        }
+    }
+
+    fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        self.document.rect(x, y, width, height, &self.fill);
+    }
+}
+
+
+/// Lay out a plant whose root is at the origin the way `plant_2d` does on a
+/// canvas, with the root just above a pot at the bottom centre, and render
+/// it as an SVG document sized to fit the plant.
+pub fn plant_svg(style: &Style, plant: &Drawing2D) -> String {
+    let (width, height) = style.plant_size(plant);
+    plant_svg_sized(style, plant, width, height)
+}
+
+/// like `plant_svg`, but render a document of the given size with the
+/// plant placed by `Style::plant_transform`
+pub fn plant_svg_sized(style: &Style, plant: &Drawing2D, width: f64, height: f64) -> String {
+    let mut svg = SvgRenderer::new(width, height);
+    scene::draw_plant(&mut svg, style, plant, width, height);
+    svg.finish()
+}
+
+/// like `plant_svg`, but paint markers with `painters`
+pub fn plant_svg_with(style: &Style, painters: &MarkerPainters, plant: &Drawing2D) -> String {
+    let (width, height) = style.plant_size(plant);
+    let mut svg = SvgRenderer::new(width, height);
+    scene::draw_plant_with(&mut svg, style, painters, plant, width, height);
+    svg.finish()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsystem::{Bearing2D, Drawing, Line2D, Marker, MarkerKind, Position2D};
+
+    #[test]
+    fn formats_numbers_compactly() {
+        assert_eq!(num(1.0), "1");
+        assert_eq!(num(1.5), "1.5");
+        assert_eq!(num(-0.001), "0");
+        assert_eq!(num(2.345678), "2.35");
+    }
+
+    #[test]
+    fn renders_a_plant() {
+        let line = Line2D {
+            start: Position2D { x: 0.0, y: 0.0 },
+            end: Position2D { x: 0.0, y: -300.0 },
+        };
+        let flower = Marker::new(MarkerKind::Flower, line.end, Bearing2D { rotation: 0.0 });
+        let plant = Drawing { lines: vec![line], markers: vec![flower], ..Drawing::default() };
+        let svg = plant_svg(&Style::default(), &plant);
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"200\" height=\"375\""));
+        // the line is drawn from just above the pot up to the top margin
+        assert!(svg.contains(r##"<path d="M100 325L100 25" fill="none" stroke="#4F7942" stroke-width="3"/>"##));
+        assert!(svg.contains(r##"<path d="M103 25A3 3 0 1 1 97 25A3 3 0 1 1 103 25Z" fill="#FDDA0D"/>"##));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn draws_partial_arcs() {
+        let mut svg = SvgRenderer::new(10.0, 10.0);
+        svg.begin_path();
+        svg.move_to(5.0, 5.0);
+        svg.arc(5.0, 5.0, 2.0, 0.0, PI / 2.0);
+        svg.stroke();
+        assert!(svg.finish().contains(r#"d="M5 5L7 5A2 2 0 0 1 5 7""#));
+    }
+
+    #[test]
+    fn escapes_colours() {
+        let mut svg = SvgDocument::new(10.0, 10.0);
+        svg.rect(0.0, 0.0, 10.0, 10.0, r#"red"/><script>&"#);
+        assert!(svg.finish().contains(r#"fill="red&quot;/&gt;&lt;script&gt;&amp;"/>"#));
+    }
+}
@@ -3,11 +3,41 @@
 #![cfg(target_arch = "wasm32")]
 
 extern crate wasm_bindgen_test;
+use wasm_bindgen::JsCast;
 use wasm_bindgen_test::*;
+use wasm_demo::lsystem::{Bearing2D, Drawing, Line2D, Marker, MarkerKind, Position2D};
+use wasm_demo::render::CanvasRenderer;
+use wasm_demo::scene::{self, Style};
 
 wasm_bindgen_test_configure!(run_in_browser);
 
 #[wasm_bindgen_test]
-fn pass() {
-    assert_eq!(1 + 1, 2);
+fn draws_a_plant_on_a_canvas() {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let canvas = document.create_element("canvas").unwrap()
+        .dyn_into::<web_sys::HtmlCanvasElement>()
+        .unwrap();
+    let ctx = canvas.get_context("2d").unwrap().unwrap()
+        .dyn_into::<web_sys::CanvasRenderingContext2d>()
+        .unwrap();
+
+    let mut renderer = CanvasRenderer::new(ctx.clone());
+    let (width, height) = renderer.size();
+    let line = Line2D {
+        start: Position2D { x: 0.0, y: 0.0 },
+        end: Position2D { x: 0.0, y: -100.0 },
+    };
+    let flower = Marker::new(MarkerKind::Flower, line.end, Bearing2D { rotation: 0.0 });
+    let plant = Drawing { lines: vec![line], markers: vec![flower], ..Drawing::default() };
+    let style = Style::default();
+    scene::draw_plant(&mut renderer, &style, &plant, width, height);
+
+    let pixel = |x: f64, y: f64| {
+        let data = ctx.get_image_data(x, y, 1.0, 1.0).unwrap().data();
+        format!("#{:02X}{:02X}{:02X}", data[0], data[1], data[2])
+    };
+    // the stem rises from the root on the pot, under an open sky
+    let root = style.plant_transform(&plant, width, height).apply(line.start);
+    assert_eq!(pixel(root.x, root.y - 20.0), style.stem_color);
+    assert_eq!(pixel(5.0, 5.0), style.sky_color.to_uppercase());
 }
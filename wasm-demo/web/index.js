@@ -1,4 +1,4 @@
-import init, { plant_2d } from "./pkg/wasm_demo.js"
+import init, { plant_2d_with_options, PlantOptions } from "./pkg/wasm_demo.js"
 
 async function run() {
     await init();
@@ -6,9 +6,19 @@ async function run() {
     let canvas = document.getElementById('canvas');
     canvas.width = window.innerWidth * 0.9;
     canvas.height = window.innerHeight * 0.9;
-    plant_2d(5, "canvas");
+
+    // `?seed=1234` regenerates a shared plant
+    let params = new URLSearchParams(window.location.search);
+    let seed = params.has('seed')
+        ? Number(params.get('seed')) >>> 0
+        : Math.floor(Math.random() * 2 ** 32);
+    // the default options draw the plant the site always has; its growth
+    // order, fit and pacing can be changed on `options` and the returned
+    // animation
+    let options = new PlantOptions();
+    let plant = plant_2d_with_options(seed, 5, "canvas", options);
+    params.set('seed', plant.seed);
+    window.history.replaceState(null, '', '?' + params.toString());
 }
 
 run();
-
-